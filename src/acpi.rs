@@ -0,0 +1,262 @@
+// --- ACPI table discovery ---
+// Finds the RSDP (preferably the copy handed over by Multiboot2, otherwise by
+// scanning the BIOS area), walks the RSDT/XSDT and decodes the MADT, which
+// tells us where the Local APIC and I/O APICs live and how ISA IRQs are wired
+// to global system interrupts.
+
+use crate::multiboot::Rsdp;
+use crate::paging::{self, PageFlags};
+
+const SDT_HEADER_LEN: usize = 36;
+// Largest table we are prepared to map and read
+const MAX_TABLE_LEN: usize = 64 * 1024;
+
+const BIOS_AREA_START: usize = 0xE0000;
+const BIOS_AREA_END: usize = 0x100000;
+
+const MAX_IO_APICS: usize = 8;
+const MAX_OVERRIDES: usize = 16;
+const MAX_CPUS: usize = 64;
+const MAX_LAPIC_NMIS: usize = 8;
+
+fn read_u8(bytes: &[u8], off: usize) -> Option<u8> {
+    bytes.get(off).copied()
+}
+
+fn read_u16(bytes: &[u8], off: usize) -> Option<u16> {
+    let b = bytes.get(off..off + 2)?;
+    Some(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(bytes: &[u8], off: usize) -> Option<u32> {
+    let b = bytes.get(off..off + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(bytes: &[u8], off: usize) -> Option<u64> {
+    let b = bytes.get(off..off + 8)?;
+    let mut raw = [0u8; 8];
+    raw.copy_from_slice(b);
+    Some(u64::from_le_bytes(raw))
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+// Make sure the physical range is reachable through the identity map
+fn map_physical(addr: usize, len: usize) -> bool {
+    paging::identity_map(addr, addr + len, PageFlags::NO_EXECUTE).is_ok()
+}
+
+unsafe fn physical_slice(addr: usize, len: usize) -> Option<&'static [u8]> {
+    if addr == 0 || !map_physical(addr, len) {
+        return None;
+    }
+    Some(core::slice::from_raw_parts(addr as *const u8, len))
+}
+
+// Map and validate the system description table at `addr`
+unsafe fn load_table(addr: usize) -> Option<&'static [u8]> {
+    let header = physical_slice(addr, SDT_HEADER_LEN)?;
+    let len = read_u32(header, 4)? as usize;
+    if !(SDT_HEADER_LEN..=MAX_TABLE_LEN).contains(&len) {
+        return None;
+    }
+    let table = physical_slice(addr, len)?;
+    if checksum_ok(table) { Some(table) } else { None }
+}
+
+#[derive(Copy, Clone)]
+struct RootTable {
+    addr: usize,
+    // XSDT entries are 64-bit, RSDT entries 32-bit
+    wide: bool,
+}
+
+static mut ROOT_TABLE: Option<RootTable> = None;
+
+fn scan_bios_area() -> Option<RootTable> {
+    let mut addr = BIOS_AREA_START;
+    while addr < BIOS_AREA_END {
+        let candidate = unsafe { core::slice::from_raw_parts(addr as *const u8, 36) };
+        if &candidate[0..8] == b"RSD PTR " && checksum_ok(&candidate[..20]) {
+            let revision = candidate[15];
+            if revision >= 2 && checksum_ok(candidate) {
+                if let Some(xsdt) = read_u64(candidate, 24).filter(|&a| a != 0) {
+                    return Some(RootTable { addr: xsdt as usize, wide: true });
+                }
+            }
+            let rsdt = read_u32(candidate, 16)?;
+            return Some(RootTable { addr: rsdt as usize, wide: false });
+        }
+        addr += 16;
+    }
+    None
+}
+
+pub fn init(rsdp: Option<Rsdp>) -> bool {
+    let root = match rsdp {
+        Some(rsdp) => match rsdp.xsdt_addr.filter(|&a| a != 0) {
+            Some(xsdt) => Some(RootTable { addr: xsdt as usize, wide: true }),
+            None => Some(RootTable { addr: rsdp.rsdt_addr as usize, wide: false }),
+        },
+        None => scan_bios_area(),
+    };
+    let root = root.filter(|root| unsafe { load_table(root.addr).is_some() });
+    unsafe { ROOT_TABLE = root; }
+    root.is_some()
+}
+
+// Look up a table such as b"APIC" or b"HPET" by signature
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    let root = unsafe { ROOT_TABLE }?;
+    let table = unsafe { load_table(root.addr) }?;
+    let entry_size = if root.wide { 8 } else { 4 };
+    let mut off = SDT_HEADER_LEN;
+    while off + entry_size <= table.len() {
+        let addr = if root.wide { read_u64(table, off)? as usize } else { read_u32(table, off)? as usize };
+        off += entry_size;
+        if let Some(candidate) = unsafe { load_table(addr) } {
+            if &candidate[0..4] == signature {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
+#[derive(Copy, Clone)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub addr: u32,
+    pub gsi_base: u32,
+}
+
+// ISA IRQ that is wired to a different GSI or with non-default polarity/trigger
+#[derive(Copy, Clone)]
+pub struct InterruptOverride {
+    pub source: u8,
+    pub gsi: u32,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+#[derive(Copy, Clone)]
+pub struct LapicNmi {
+    // 0xFF means every processor
+    pub processor: u8,
+    pub lint: u8,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+#[derive(Copy, Clone)]
+pub struct Madt {
+    pub local_apic_addr: u64,
+    pub has_legacy_pics: bool,
+    pub io_apics: [IoApicInfo; MAX_IO_APICS],
+    pub io_apic_count: usize,
+    pub overrides: [InterruptOverride; MAX_OVERRIDES],
+    pub override_count: usize,
+    pub cpu_apic_ids: [u8; MAX_CPUS],
+    pub cpu_count: usize,
+    pub nmis: [LapicNmi; MAX_LAPIC_NMIS],
+    pub nmi_count: usize,
+}
+
+impl Madt {
+    pub fn io_apics(&self) -> &[IoApicInfo] {
+        &self.io_apics[..self.io_apic_count]
+    }
+
+    pub fn overrides(&self) -> &[InterruptOverride] {
+        &self.overrides[..self.override_count]
+    }
+
+    pub fn cpu_apic_ids(&self) -> &[u8] {
+        &self.cpu_apic_ids[..self.cpu_count]
+    }
+
+    pub fn nmis(&self) -> &[LapicNmi] {
+        &self.nmis[..self.nmi_count]
+    }
+}
+
+// MPS INTI flags: polarity in bits 0-1, trigger mode in bits 2-3
+fn inti_active_low(flags: u16) -> bool {
+    flags & 0x3 == 0x3
+}
+
+fn inti_level_triggered(flags: u16) -> bool {
+    (flags >> 2) & 0x3 == 0x3
+}
+
+pub fn parse_madt() -> Option<Madt> {
+    let table = find_table(b"APIC")?;
+    let mut madt = Madt {
+        local_apic_addr: read_u32(table, 36)? as u64,
+        has_legacy_pics: read_u32(table, 40)? & 1 != 0,
+        io_apics: [IoApicInfo { id: 0, addr: 0, gsi_base: 0 }; MAX_IO_APICS],
+        io_apic_count: 0,
+        overrides: [InterruptOverride { source: 0, gsi: 0, active_low: false, level_triggered: false }; MAX_OVERRIDES],
+        override_count: 0,
+        cpu_apic_ids: [0; MAX_CPUS],
+        cpu_count: 0,
+        nmis: [LapicNmi { processor: 0, lint: 0, active_low: false, level_triggered: false }; MAX_LAPIC_NMIS],
+        nmi_count: 0,
+    };
+
+    let mut off = 44;
+    while off + 2 <= table.len() {
+        let typ = read_u8(table, off)?;
+        let len = read_u8(table, off + 1)? as usize;
+        if len < 2 {
+            break;
+        }
+        let entry = table.get(off..off + len)?;
+        off += len;
+        match typ {
+            // Processor Local APIC, only enabled or online-capable CPUs
+            0 => {
+                let flags = read_u32(entry, 4)?;
+                if flags & 0x3 != 0 && madt.cpu_count < MAX_CPUS {
+                    madt.cpu_apic_ids[madt.cpu_count] = read_u8(entry, 3)?;
+                    madt.cpu_count += 1;
+                }
+            }
+            1 if madt.io_apic_count < MAX_IO_APICS => {
+                madt.io_apics[madt.io_apic_count] = IoApicInfo {
+                    id: read_u8(entry, 2)?,
+                    addr: read_u32(entry, 4)?,
+                    gsi_base: read_u32(entry, 8)?,
+                };
+                madt.io_apic_count += 1;
+            }
+            2 if madt.override_count < MAX_OVERRIDES => {
+                let flags = read_u16(entry, 8)?;
+                madt.overrides[madt.override_count] = InterruptOverride {
+                    source: read_u8(entry, 3)?,
+                    gsi: read_u32(entry, 4)?,
+                    active_low: inti_active_low(flags),
+                    level_triggered: inti_level_triggered(flags),
+                };
+                madt.override_count += 1;
+            }
+            4 if madt.nmi_count < MAX_LAPIC_NMIS => {
+                let flags = read_u16(entry, 3)?;
+                madt.nmis[madt.nmi_count] = LapicNmi {
+                    processor: read_u8(entry, 2)?,
+                    lint: read_u8(entry, 5)?,
+                    active_low: inti_active_low(flags),
+                    level_triggered: inti_level_triggered(flags),
+                };
+                madt.nmi_count += 1;
+            }
+            // 64-bit Local APIC address override
+            5 => madt.local_apic_addr = read_u64(entry, 4)?,
+            _ => {}
+        }
+    }
+    Some(madt)
+}
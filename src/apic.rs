@@ -0,0 +1,239 @@
+// --- Local APIC and I/O APIC ---
+// The Local APIC receives interrupts for this CPU and takes the EOI; the
+// I/O APIC(s) described by the MADT turn device lines into messages. ISA IRQs
+// are routed to the same vectors the remapped 8259 used (32-47), so the IDT
+// stubs and the irq dispatch table work unchanged with either controller.
+
+use core::arch::x86_64::__cpuid;
+
+use crate::acpi::Madt;
+use crate::paging;
+use crate::pic::{IRQ_BASE, IRQ_COUNT};
+use crate::{rdmsr, wrmsr};
+
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+// Local APIC register offsets
+const LAPIC_ID: usize = 0x20;
+const LAPIC_TPR: usize = 0x80;
+const LAPIC_EOI: usize = 0xB0;
+const LAPIC_SVR: usize = 0xF0;
+const LAPIC_ESR: usize = 0x280;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_LVT_LINT0: usize = 0x350;
+const LAPIC_LVT_LINT1: usize = 0x360;
+const LAPIC_LVT_ERROR: usize = 0x370;
+
+const SVR_APIC_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_LEVEL: u32 = 1 << 15;
+const LVT_ACTIVE_LOW: u32 = 1 << 13;
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+
+// I/O APIC registers
+const IOAPIC_REGSEL: usize = 0x00;
+const IOAPIC_WINDOW: usize = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION: u32 = 0x10;
+
+const REDIR_MASKED: u32 = 1 << 16;
+const REDIR_LEVEL: u32 = 1 << 15;
+const REDIR_ACTIVE_LOW: u32 = 1 << 13;
+
+const MAX_IO_APICS: usize = 8;
+const MMIO_SIZE: usize = 0x1000;
+
+#[derive(Copy, Clone)]
+struct IoApic {
+    base: usize,
+    gsi_base: u32,
+    entries: u32,
+}
+
+// Where an ISA IRQ ends up after applying the MADT source overrides
+#[derive(Copy, Clone)]
+struct IsaRoute {
+    gsi: u32,
+    active_low: bool,
+    level_triggered: bool,
+}
+
+static mut LAPIC_BASE: usize = 0;
+static mut IO_APICS: [IoApic; MAX_IO_APICS] = [IoApic { base: 0, gsi_base: 0, entries: 0 }; MAX_IO_APICS];
+static mut IO_APIC_COUNT: usize = 0;
+// None for ISA lines whose GSI has been taken over by another IRQ
+static mut ISA_ROUTES: [Option<IsaRoute>; IRQ_COUNT as usize] = [None; IRQ_COUNT as usize];
+
+pub fn is_supported() -> bool {
+    __cpuid(1).edx & (1 << 9) != 0
+}
+
+unsafe fn lapic_read(reg: usize) -> u32 {
+    core::ptr::read_volatile((LAPIC_BASE + reg) as *const u32)
+}
+
+unsafe fn lapic_write(reg: usize, val: u32) {
+    core::ptr::write_volatile((LAPIC_BASE + reg) as *mut u32, val);
+}
+
+unsafe fn ioapic_read(ioapic: &IoApic, reg: u32) -> u32 {
+    core::ptr::write_volatile((ioapic.base + IOAPIC_REGSEL) as *mut u32, reg);
+    core::ptr::read_volatile((ioapic.base + IOAPIC_WINDOW) as *const u32)
+}
+
+unsafe fn ioapic_write(ioapic: &IoApic, reg: u32, val: u32) {
+    core::ptr::write_volatile((ioapic.base + IOAPIC_REGSEL) as *mut u32, reg);
+    core::ptr::write_volatile((ioapic.base + IOAPIC_WINDOW) as *mut u32, val);
+}
+
+unsafe fn ioapic_for_gsi(gsi: u32) -> Option<IoApic> {
+    IO_APICS[..IO_APIC_COUNT]
+        .iter()
+        .find(|ioapic| gsi >= ioapic.gsi_base && gsi < ioapic.gsi_base + ioapic.entries)
+        .copied()
+}
+
+unsafe fn write_redirection(gsi: u32, low: u32, high: u32) {
+    if let Some(ioapic) = ioapic_for_gsi(gsi) {
+        let reg = IOAPIC_REDIRECTION + (gsi - ioapic.gsi_base) * 2;
+        // Mask while the entry is half-written
+        ioapic_write(&ioapic, reg, REDIR_MASKED);
+        ioapic_write(&ioapic, reg + 1, high);
+        ioapic_write(&ioapic, reg, low);
+    }
+}
+
+unsafe fn update_redirection_mask(gsi: u32, masked: bool) {
+    if let Some(ioapic) = ioapic_for_gsi(gsi) {
+        let reg = IOAPIC_REDIRECTION + (gsi - ioapic.gsi_base) * 2;
+        let low = ioapic_read(&ioapic, reg);
+        let low = if masked { low | REDIR_MASKED } else { low & !REDIR_MASKED };
+        ioapic_write(&ioapic, reg, low);
+    }
+}
+
+pub fn local_apic_id() -> u8 {
+    unsafe { (lapic_read(LAPIC_ID) >> 24) as u8 }
+}
+
+unsafe fn init_local_apic(madt: &Madt) {
+    // Only let through interrupts of priority class 0 and above, i.e. all
+    lapic_write(LAPIC_TPR, 0);
+    lapic_write(LAPIC_LVT_TIMER, LVT_MASKED);
+    lapic_write(LAPIC_LVT_ERROR, LVT_MASKED);
+    lapic_write(LAPIC_LVT_LINT0, LVT_MASKED);
+    lapic_write(LAPIC_LVT_LINT1, LVT_MASKED);
+    for nmi in madt.nmis() {
+        let mut lvt = LVT_DELIVERY_NMI;
+        if nmi.active_low {
+            lvt |= LVT_ACTIVE_LOW;
+        }
+        if nmi.level_triggered {
+            lvt |= LVT_LEVEL;
+        }
+        match nmi.lint {
+            0 => lapic_write(LAPIC_LVT_LINT0, lvt),
+            1 => lapic_write(LAPIC_LVT_LINT1, lvt),
+            _ => {}
+        }
+    }
+    // Clear any stale errors (the ESR needs a write before it is read)
+    lapic_write(LAPIC_ESR, 0);
+    lapic_write(LAPIC_ESR, 0);
+    lapic_write(LAPIC_SVR, SPURIOUS_VECTOR as u32 | SVR_APIC_ENABLE);
+    end_of_interrupt();
+}
+
+// Bring up the Local APIC and route the ISA IRQs through the I/O APIC(s).
+// Every ISA line starts masked. Returns false if there is no usable APIC.
+pub fn init(madt: &Madt) -> bool {
+    if !is_supported() || madt.io_apics().is_empty() {
+        return false;
+    }
+    unsafe {
+        let msr = rdmsr(IA32_APIC_BASE);
+        let base = match msr & APIC_BASE_ADDR_MASK {
+            0 => madt.local_apic_addr as usize,
+            addr => addr as usize,
+        };
+        if paging::map_mmio(base, MMIO_SIZE).is_err() {
+            return false;
+        }
+        wrmsr(IA32_APIC_BASE, msr | APIC_BASE_ENABLE);
+        LAPIC_BASE = base;
+
+        IO_APIC_COUNT = 0;
+        for info in madt.io_apics().iter().take(MAX_IO_APICS) {
+            let base = info.addr as usize;
+            if paging::map_mmio(base, MMIO_SIZE).is_err() {
+                continue;
+            }
+            let mut ioapic = IoApic { base, gsi_base: info.gsi_base, entries: 0 };
+            ioapic.entries = ((ioapic_read(&ioapic, IOAPIC_VERSION) >> 16) & 0xFF) + 1;
+            for entry in 0..ioapic.entries {
+                ioapic_write(&ioapic, IOAPIC_REDIRECTION + entry * 2, REDIR_MASKED);
+            }
+            IO_APICS[IO_APIC_COUNT] = ioapic;
+            IO_APIC_COUNT += 1;
+        }
+        if IO_APIC_COUNT == 0 {
+            return false;
+        }
+
+        init_local_apic(madt);
+
+        // ISA IRQs are edge-triggered, active high and identity-mapped unless
+        // the firmware says otherwise. An override such as IRQ0 -> GSI2 also
+        // means the line that would have been identity-mapped to GSI2 is gone.
+        for irq in 0..IRQ_COUNT {
+            let shadowed = madt.overrides().iter().any(|ovr| ovr.gsi == irq as u32 && ovr.source != irq);
+            ISA_ROUTES[irq as usize] = if shadowed {
+                None
+            } else {
+                Some(IsaRoute { gsi: irq as u32, active_low: false, level_triggered: false })
+            };
+        }
+        for ovr in madt.overrides() {
+            if ovr.source < IRQ_COUNT {
+                ISA_ROUTES[ovr.source as usize] = Some(IsaRoute {
+                    gsi: ovr.gsi,
+                    active_low: ovr.active_low,
+                    level_triggered: ovr.level_triggered,
+                });
+            }
+        }
+        let destination = (local_apic_id() as u32) << 24;
+        for irq in 0..IRQ_COUNT {
+            let Some(route) = ISA_ROUTES[irq as usize] else { continue };
+            let mut low = (IRQ_BASE + irq) as u32 | REDIR_MASKED;
+            if route.active_low {
+                low |= REDIR_ACTIVE_LOW;
+            }
+            if route.level_triggered {
+                low |= REDIR_LEVEL;
+            }
+            write_redirection(route.gsi, low, destination);
+        }
+    }
+    true
+}
+
+pub fn mask(irq: u8) {
+    if let Some(Some(route)) = unsafe { ISA_ROUTES.get(irq as usize) } {
+        unsafe { update_redirection_mask(route.gsi, true); }
+    }
+}
+
+pub fn unmask(irq: u8) {
+    if let Some(Some(route)) = unsafe { ISA_ROUTES.get(irq as usize) } {
+        unsafe { update_redirection_mask(route.gsi, false); }
+    }
+}
+
+pub fn end_of_interrupt() {
+    unsafe { lapic_write(LAPIC_EOI, 0); }
+}
@@ -9,6 +9,10 @@ section .text
 bits 32
 
 multiboot_entry:
+    ; Preserve the Multiboot2 magic (eax) for _start. ebx holds the boot
+    ; information address and is left untouched until long_mode_start.
+    mov esi, eax
+
     ; Set up stack (temporary, 16 KiB at 0x90000)
     mov esp, 0x90000
 
@@ -0,0 +1,258 @@
+// --- Bochs Graphics Adapter ---
+// The display of Bochs, QEMU's `-vga std` and VirtualBox: a VGA with extra
+// registers, behind the index/data ports 0x1CE/0x1CF, that set resolutions
+// far beyond VGA's at up to 32 bits per pixel. The screen is then a linear
+// framebuffer in video memory, whose physical address is the adapter's
+// first PCI BAR.
+//
+// Modes here are 32 bpp, one 0x00RRGGBB dword per pixel. The drawing API
+// still takes 8-bit color indices; they become RGB through a copy of the
+// DAC palette taken when the mode is set (call sync_palette after changing
+// the DAC). Like on any true-color screen, a palette change does not
+// recolor what is already drawn, and reading a pixel back finds the index
+// whose color it has.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use crate::{inw, outw};
+use crate::{paging, palette, pci};
+
+const INDEX_PORT: u16 = 0x01CE;
+const DATA_PORT: u16 = 0x01CF;
+
+const REG_ID: u16 = 0x00;
+const REG_XRES: u16 = 0x01;
+const REG_YRES: u16 = 0x02;
+const REG_BPP: u16 = 0x03;
+const REG_ENABLE: u16 = 0x04;
+const REG_VIRT_WIDTH: u16 = 0x06;
+const REG_X_OFFSET: u16 = 0x08;
+const REG_Y_OFFSET: u16 = 0x09;
+
+// 0xB0C0 plus the interface version; 32 bpp and the linear framebuffer
+// arrived in version 2
+const ID_MIN: u16 = 0xB0C2;
+const ID_MAX: u16 = 0xB0CF;
+
+const ENABLED: u16 = 0x01;
+// While set, the resolution registers read back the adapter's maximums
+const GET_CAPS: u16 = 0x02;
+const LFB_ENABLED: u16 = 0x40;
+
+// PCI IDs of the emulated adapter: Bochs/QEMU, then VirtualBox
+const PCI_IDS: [(u16, u16); 2] = [(0x1234, 0x1111), (0x80EE, 0xBEEF)];
+
+pub const BPP: u8 = 32;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BgaError {
+    NotPresent,
+    // No PCI device, or its BAR is unassigned
+    NoFramebuffer,
+    UnsupportedMode(usize, usize, u8),
+    MapFailed,
+}
+
+impl BgaError {
+    pub fn description(&self) -> &'static str {
+        match self {
+            BgaError::NotPresent => "no Bochs graphics adapter",
+            BgaError::NoFramebuffer => "adapter framebuffer not found on PCI",
+            BgaError::UnsupportedMode(..) => "mode not supported by the adapter",
+            BgaError::MapFailed => "could not map the framebuffer",
+        }
+    }
+}
+
+static mut FRAMEBUFFER: *mut u32 = core::ptr::null_mut();
+// Bytes mapped at FRAMEBUFFER: the whole BAR, i.e. all of video memory
+static mut FRAMEBUFFER_SIZE: usize = 0;
+static mut WIDTH: usize = 0;
+static mut HEIGHT: usize = 0;
+// Pixels from one row to the next
+static mut PITCH: usize = 0;
+// RGB for each color index
+static mut COLORS: [u32; palette::COLORS] = [0; palette::COLORS];
+
+fn write_register(index: u16, value: u16) {
+    unsafe {
+        outw(INDEX_PORT, index);
+        outw(DATA_PORT, value);
+    }
+}
+
+fn read_register(index: u16) -> u16 {
+    unsafe {
+        outw(INDEX_PORT, index);
+        inw(DATA_PORT)
+    }
+}
+
+// Interface version of the adapter, if there is one we can drive
+pub fn version() -> Option<u16> {
+    let id = read_register(REG_ID);
+    if (ID_MIN..=ID_MAX).contains(&id) { Some(id - 0xB0C0) } else { None }
+}
+
+pub fn is_present() -> bool {
+    version().is_some()
+}
+
+// Largest resolution the adapter (and its video memory) allows
+pub fn max_resolution() -> (usize, usize) {
+    let enable = read_register(REG_ENABLE);
+    write_register(REG_ENABLE, enable | GET_CAPS);
+    let max = (read_register(REG_XRES) as usize, read_register(REG_YRES) as usize);
+    write_register(REG_ENABLE, enable);
+    max
+}
+
+// Find the framebuffer on PCI and map all of it, once. Mapping the whole BAR
+// keeps any row padding the adapter picks inside the mapping.
+fn map_framebuffer() -> Result<(), BgaError> {
+    if unsafe { !FRAMEBUFFER.is_null() } {
+        return Ok(());
+    }
+    let (addr, size) = PCI_IDS
+        .iter()
+        .find_map(|&(vendor, device)| {
+            let device = pci::find(vendor, device)?;
+            device.enable_memory();
+            Some((device.memory_bar(0)?, device.memory_bar_size(0)?))
+        })
+        .ok_or(BgaError::NoFramebuffer)?;
+    paging::map_mmio(addr, size).map_err(|_| BgaError::MapFailed)?;
+    unsafe {
+        FRAMEBUFFER = addr as *mut u32;
+        FRAMEBUFFER_SIZE = size;
+    }
+    Ok(())
+}
+
+// Make sure `width` x `height` can be set and the framebuffer is mapped.
+// Called before anything about the current mode is torn down, so a failure
+// leaves the screen as it was.
+pub fn check_mode(width: usize, height: usize, bpp: u8) -> Result<(), BgaError> {
+    if !is_present() {
+        return Err(BgaError::NotPresent);
+    }
+    let (max_width, max_height) = max_resolution();
+    if bpp != BPP || width == 0 || height == 0 || width > max_width || height > max_height {
+        return Err(BgaError::UnsupportedMode(width, height, bpp));
+    }
+    map_framebuffer()?;
+    if width * height * 4 > unsafe { FRAMEBUFFER_SIZE } {
+        return Err(BgaError::UnsupportedMode(width, height, bpp));
+    }
+    Ok(())
+}
+
+// Switch to `width` x `height` at 32 bpp; check_mode must have accepted it.
+// The adapter clears the screen, and the VGA registers stop mattering until
+// disable(). Returns the number of rows that can be drawn, which is less
+// than `height` if padded rows would run past the mapped video memory.
+pub fn set_mode(width: usize, height: usize) -> usize {
+    write_register(REG_ENABLE, 0);
+    write_register(REG_XRES, width as u16);
+    write_register(REG_YRES, height as u16);
+    write_register(REG_BPP, BPP as u16);
+    write_register(REG_VIRT_WIDTH, width as u16);
+    write_register(REG_X_OFFSET, 0);
+    write_register(REG_Y_OFFSET, 0);
+    write_register(REG_ENABLE, ENABLED | LFB_ENABLED);
+    unsafe {
+        WIDTH = width;
+        // The adapter may pad rows; it reports the width it settled on.
+        // Rows that would end past the mapping are dropped from the mode.
+        PITCH = (read_register(REG_VIRT_WIDTH) as usize).max(width);
+        HEIGHT = height.min(FRAMEBUFFER_SIZE / (PITCH * 4));
+    }
+    sync_palette();
+    unsafe { HEIGHT }
+}
+
+// Hand the display back to the VGA registers
+pub fn disable() {
+    write_register(REG_ENABLE, 0);
+}
+
+// Take the colors for indices 0-255 from the DAC
+pub fn sync_palette() {
+    let palette = palette::save();
+    unsafe {
+        for (color, rgb) in COLORS.iter_mut().zip(palette.iter()) {
+            *color = (rgb.r as u32) << 16 | (rgb.g as u32) << 8 | rgb.b as u32;
+        }
+    }
+}
+
+fn rgb(color: u8) -> u32 {
+    unsafe { COLORS[color as usize] }
+}
+
+fn pixel(x: usize, y: usize) -> *mut u32 {
+    unsafe { FRAMEBUFFER.add(y * PITCH + x) }
+}
+
+fn size() -> (usize, usize) {
+    unsafe { (WIDTH, HEIGHT) }
+}
+
+pub fn set_pixel(x: usize, y: usize, color: u8) {
+    let (width, height) = size();
+    if x >= width || y >= height {
+        return;
+    }
+    unsafe { write_volatile(pixel(x, y), rgb(color)); }
+}
+
+// The index whose color the pixel has; pixels in a color that is no longer
+// in the palette read as 0
+pub fn get_pixel(x: usize, y: usize) -> u8 {
+    let (width, height) = size();
+    if x >= width || y >= height {
+        return 0;
+    }
+    let value = unsafe { read_volatile(pixel(x, y)) };
+    unsafe { COLORS.iter().position(|&color| color == value).unwrap_or(0) as u8 }
+}
+
+pub fn fill_rect(x: usize, y: usize, w: usize, h: usize, color: u8) {
+    let (width, height) = size();
+    let end_x = x.saturating_add(w).min(width);
+    let end_y = y.saturating_add(h).min(height);
+    let value = rgb(color);
+    for row in y..end_y {
+        for col in x..end_x {
+            unsafe { write_volatile(pixel(col, row), value); }
+        }
+    }
+}
+
+pub fn clear(color: u8) {
+    let (width, height) = size();
+    fill_rect(0, 0, width, height, color);
+}
+
+// Set the pixels of a 1bpp bitmap (see crate::bitmap_bit) in `color`
+pub fn draw_bitmap(x: usize, y: usize, w: usize, h: usize, bitmap: &[u8], color: u8) {
+    let (visible_w, visible_h) = crate::clip_area(x, y, w, h);
+    let value = rgb(color);
+    for row in 0..visible_h {
+        for col in 0..visible_w {
+            if crate::bitmap_bit(bitmap, w, col, row) {
+                unsafe { write_volatile(pixel(x + col, y + row), value); }
+            }
+        }
+    }
+}
+
+// Copy an area already clipped by fb_blit. Rows go in crate::copy_direction
+// order; within a row ptr::copy copes with overlap itself.
+pub fn copy_rect(src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, w: usize, h: usize) {
+    let (rows_up, _) = crate::copy_direction(src_x, src_y, dst_x, dst_y);
+    for i in 0..h {
+        let row = if rows_up { h - 1 - i } else { i };
+        unsafe { core::ptr::copy(pixel(src_x, src_y + row), pixel(dst_x, dst_y + row), w); }
+    }
+}
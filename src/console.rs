@@ -0,0 +1,83 @@
+// --- Formatted console output ---
+// A core::fmt::Write adapter over the VGA text buffer plus the print! family
+// of macros, so numbers and messages can be formatted instead of pieced
+// together from vga_print calls. Output is written with interrupts disabled,
+// so a handler that prints cannot interleave with (or corrupt the cursor of)
+// the code it interrupted. Like vga_print, everything is mirrored to serial.
+
+use core::fmt;
+
+use crate::irq;
+
+pub const COLOR_DEFAULT: u8 = 0x2f;
+pub const COLOR_ERROR: u8 = 0x4f;
+
+// Shown for characters the VGA font has no glyph for (a small square)
+const REPLACEMENT: u8 = 0xFE;
+
+pub struct Writer {
+    pub color: u8,
+}
+
+impl Writer {
+    pub const fn new(color: u8) -> Writer {
+        Writer { color }
+    }
+}
+
+// Translate the few non-ASCII characters code page 437 can display
+fn to_cp437(c: char) -> u8 {
+    match c {
+        ' '..='~' | '\n' | '\r' | '\t' | '\x08' | '\x1b' => c as u8,
+        'ä' => 0x84, 'Ä' => 0x8E, 'ö' => 0x94, 'Ö' => 0x99,
+        'ü' => 0x81, 'Ü' => 0x9A, 'ß' => 0xE1, 'é' => 0x82,
+        '£' => 0x9C, '°' => 0xF8, '²' => 0xFD, 'µ' => 0xE6,
+        '§' => 0x15, '¬' => 0xAA,
+        _ => REPLACEMENT,
+    }
+}
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.is_ascii() {
+            crate::vga_print(s, self.color);
+        } else {
+            crate::serial::write_str(s);
+            for c in s.chars() {
+                crate::vga_write(&[to_cp437(c)], self.color);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(color: u8, args: fmt::Arguments) {
+    use core::fmt::Write;
+    irq::without_interrupts(|| {
+        let _ = Writer::new(color).write_fmt(args);
+    });
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::_print($crate::console::COLOR_DEFAULT, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+// Like print!, with a VGA attribute byte as the first argument
+#[macro_export]
+macro_rules! print_color {
+    ($color:expr, $($arg:tt)*) => ($crate::console::_print($color, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println_color {
+    ($color:expr) => ($crate::print_color!($color, "\n"));
+    ($color:expr, $($arg:tt)*) => ($crate::print_color!($color, "{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,157 @@
+// --- Framebuffer text console ---
+// A terminal (40x25 in Mode 13h) drawn with the 8x8 bitmap font while a graphics mode is
+// active, so print!, the log and panic messages stay visible after the VGA
+// text screen is gone. Output goes through the same escape parser as the
+// text terminals (SGR colors, cursor position, clear screen). Text attribute
+// nibbles map onto the first 16 palette entries.
+
+use crate::irq;
+use crate::vt::{AnsiAction, AnsiState};
+use crate::{fb_blit_bitmap_enhanced, fb_draw_rect_enhanced, fb_scroll_up, get_font_char, get_palette_color};
+use crate::{fb_height, fb_width};
+
+const GLYPH_SIZE: usize = 8;
+// Enough for 1280x1024
+const MAX_COLUMNS: usize = 160;
+const MAX_ROWS: usize = 128;
+const TAB_WIDTH: usize = 8;
+
+static mut ACTIVE: bool = false;
+// Size of the console in the current mode
+static mut COLUMNS: usize = 0;
+static mut ROWS: usize = 0;
+static mut ROW: usize = 0;
+static mut COL: usize = 0;
+// Attribute << 8 | character, needed to redraw a cell under the cursor.
+// Rows are COLUMNS apart.
+static mut CELLS: [u16; MAX_COLUMNS * MAX_ROWS] = [0; MAX_COLUMNS * MAX_ROWS];
+static mut ANSI: AnsiState = AnsiState::new();
+
+// Start routing console output to the framebuffer, sized to the current
+// mode. Called by the mode switch; the screen content itself is left alone.
+pub fn activate() {
+    unsafe {
+        COLUMNS = (fb_width() / GLYPH_SIZE).min(MAX_COLUMNS);
+        ROWS = (fb_height() / GLYPH_SIZE).min(MAX_ROWS);
+        CELLS.fill(0);
+        ROW = 0;
+        COL = 0;
+        // Nothing to draw on in a mode without a drawable framebuffer
+        ACTIVE = COLUMNS > 0 && ROWS > 0;
+    }
+}
+
+pub fn deactivate() {
+    unsafe { ACTIVE = false; }
+}
+
+pub fn is_active() -> bool {
+    unsafe { ACTIVE }
+}
+
+fn draw_cell(row: usize, col: usize) {
+    let cell = unsafe { CELLS[row * COLUMNS + col] };
+    let attribute = (cell >> 8) as u8;
+    let (x, y) = (col * GLYPH_SIZE, row * GLYPH_SIZE);
+    fb_draw_rect_enhanced(x, y, GLYPH_SIZE, GLYPH_SIZE, get_palette_color(attribute >> 4));
+    let glyph = get_font_char(cell as u8);
+    fb_blit_bitmap_enhanced(x, y, GLYPH_SIZE, GLYPH_SIZE, &glyph, get_palette_color(attribute & 0x0F));
+}
+
+// Underline the cell the next character goes to
+fn draw_cursor() {
+    let (row, col) = unsafe { (ROW, COL) };
+    let attribute = unsafe { (CELLS[row * COLUMNS + col] >> 8) as u8 };
+    // Blank cells have no attribute yet; use white
+    let color = if attribute == 0 { 15 } else { attribute & 0x0F };
+    fb_draw_rect_enhanced(col * GLYPH_SIZE, row * GLYPH_SIZE + GLYPH_SIZE - 1, GLYPH_SIZE, 1, get_palette_color(color));
+}
+
+fn scroll() {
+    unsafe {
+        CELLS.copy_within(COLUMNS..COLUMNS * ROWS, 0);
+        CELLS[COLUMNS * (ROWS - 1)..COLUMNS * ROWS].fill(0);
+    }
+    fb_scroll_up(GLYPH_SIZE, get_palette_color(0));
+}
+
+fn csi(command: u8) {
+    unsafe {
+        let ansi = &*core::ptr::addr_of!(ANSI);
+        match command {
+            b'H' | b'f' => {
+                ROW = (ansi.param_or(0, 1) - 1).min(ROWS - 1);
+                COL = (ansi.param_or(1, 1) - 1).min(COLUMNS - 1);
+            }
+            b'J' if ansi.params[0] == 2 => {
+                CELLS.fill(0);
+                fb_draw_rect_enhanced(0, 0, fb_width(), fb_height(), get_palette_color(0));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn put(byte: u8, color: u8) {
+    unsafe {
+        match byte {
+            b'\n' => {
+                ROW += 1;
+                COL = 0;
+            }
+            b'\r' => COL = 0,
+            0x08 => COL = COL.saturating_sub(1),
+            b'\t' => COL = ((COL / TAB_WIDTH + 1) * TAB_WIDTH).min(COLUMNS - 1),
+            b => {
+                let attribute = (*core::ptr::addr_of!(ANSI)).attribute(color);
+                CELLS[ROW * COLUMNS + COL] = (attribute as u16) << 8 | b as u16;
+                draw_cell(ROW, COL);
+                COL += 1;
+                if COL >= COLUMNS {
+                    ROW += 1;
+                    COL = 0;
+                }
+            }
+        }
+        if ROW >= ROWS {
+            scroll();
+            ROW = ROWS - 1;
+        }
+    }
+}
+
+pub fn write(bytes: &[u8], color: u8) {
+    if is_active() {
+        // Handlers print too; keep them from drawing into a half-done line
+        irq::without_interrupts(|| draw(bytes, color));
+    }
+}
+
+fn draw(bytes: &[u8], color: u8) {
+    // The mouse cursor's save-under would be stale after drawing beneath it
+    let mouse_cursor = unsafe { crate::MOUSE_CURSOR_VISIBLE };
+    if mouse_cursor {
+        crate::fb_cursor_hide();
+    }
+    unsafe {
+        // Erase the old cursor by redrawing its cell
+        draw_cell(ROW, COL);
+        for &byte in bytes {
+            match (*core::ptr::addr_of_mut!(ANSI)).feed(byte) {
+                AnsiAction::None => {}
+                AnsiAction::Csi(command) => csi(command),
+                AnsiAction::Byte(byte) => put(byte, color),
+            }
+        }
+    }
+    draw_cursor();
+    // With double buffering the text is drawn into the back buffer; show it
+    // now so messages (e.g. a panic) cannot be lost waiting for a frame.
+    // Interrupts are off here, so don't wait for retrace.
+    if unsafe { crate::DOUBLE_BUFFER_ENABLED } {
+        crate::fb_present(false);
+    }
+    if mouse_cursor {
+        crate::fb_cursor_show();
+    }
+}
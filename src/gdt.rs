@@ -0,0 +1,138 @@
+// --- Global Descriptor Table and Task State Segment ---
+// Replaces the three-entry gdt64 from boot.asm. The TSS provides the
+// Interrupt Stack Table: the CPU switches to a known-good stack for the IDT
+// entries that name an IST slot, so a double fault caused by a blown kernel
+// stack can still be reported instead of escalating to a triple fault.
+
+use core::arch::asm;
+use core::mem::size_of;
+
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+// User data comes before user code so SYSRET can derive both selectors
+pub const USER_DATA_SELECTOR: u16 = 0x18 | 3;
+pub const USER_CODE_SELECTOR: u16 = 0x20 | 3;
+pub const TSS_SELECTOR: u16 = 0x28;
+
+// IST slots are numbered 1..=7 in IDT entries
+pub const DOUBLE_FAULT_IST: u8 = 1;
+pub const NMI_IST: u8 = 2;
+pub const MACHINE_CHECK_IST: u8 = 3;
+
+const IST_STACK_SIZE: usize = 16 * 1024;
+const IST_STACK_COUNT: usize = 3;
+// Stack used when an interrupt arrives while running in ring 3
+const KERNEL_STACK_SIZE: usize = 16 * 1024;
+
+#[repr(C, packed)]
+struct TaskStateSegment {
+    reserved0: u32,
+    rsp: [u64; 3],
+    reserved1: u64,
+    ist: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+    iomap_base: u16,
+}
+
+#[repr(C, align(16))]
+struct Stack<const N: usize>([u8; N]);
+
+static mut TSS: TaskStateSegment = TaskStateSegment {
+    reserved0: 0,
+    rsp: [0; 3],
+    reserved1: 0,
+    ist: [0; 7],
+    reserved2: 0,
+    reserved3: 0,
+    // No I/O permission bitmap: point past the end of the segment
+    iomap_base: size_of::<TaskStateSegment>() as u16,
+};
+
+static mut IST_STACKS: [Stack<IST_STACK_SIZE>; IST_STACK_COUNT] =
+    [const { Stack([0; IST_STACK_SIZE]) }; IST_STACK_COUNT];
+static mut PRIVILEGE_STACK: Stack<KERNEL_STACK_SIZE> = Stack([0; KERNEL_STACK_SIZE]);
+
+// null, kernel code, kernel data, user data, user code, TSS (two slots)
+static mut GDT: [u64; 7] = [
+    0,
+    0x0020_9A00_0000_0000, // 64-bit code, DPL 0
+    0x0000_9200_0000_0000, // data, DPL 0
+    0x0000_F200_0000_0000, // data, DPL 3
+    0x0020_FA00_0000_0000, // 64-bit code, DPL 3
+    0,
+    0,
+];
+
+#[repr(C, packed)]
+struct GdtPtr {
+    limit: u16,
+    base: u64,
+}
+
+// Build the 16-byte system descriptor for an available 64-bit TSS
+fn tss_descriptor(base: u64, limit: u64) -> (u64, u64) {
+    let low = (limit & 0xFFFF)
+        | ((base & 0xFF_FFFF) << 16)
+        | (0x89 << 40) // present, type = 64-bit TSS (available)
+        | (((limit >> 16) & 0xF) << 48)
+        | (((base >> 24) & 0xFF) << 56);
+    let high = base >> 32;
+    (low, high)
+}
+
+fn stack_top(stack: *const u8, size: usize) -> u64 {
+    (stack as usize + size) as u64
+}
+
+pub fn init() {
+    unsafe {
+        // The TSS is packed, so its arrays are built locally and copied in
+        let mut ist = [0u64; 7];
+        for (slot, stack) in IST_STACKS.iter().enumerate() {
+            ist[slot] = stack_top(stack.0.as_ptr(), IST_STACK_SIZE);
+        }
+        TSS.ist = ist;
+        TSS.rsp = [stack_top(PRIVILEGE_STACK.0.as_ptr(), KERNEL_STACK_SIZE), 0, 0];
+
+        let tss_base = &raw const TSS as u64;
+        let (low, high) = tss_descriptor(tss_base, size_of::<TaskStateSegment>() as u64 - 1);
+        GDT[5] = low;
+        GDT[6] = high;
+
+        let gdt_ptr = GdtPtr {
+            limit: (size_of::<[u64; 7]>() - 1) as u16,
+            base: &raw const GDT as u64,
+        };
+        asm!("lgdt [{}]", in(reg) &gdt_ptr, options(readonly, nostack, preserves_flags));
+
+        // Reload CS with a far return, then the data segment registers
+        asm!(
+            "push {code}",
+            "lea {tmp}, [rip + 2f]",
+            "push {tmp}",
+            "retfq",
+            "2:",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "mov ss, {data:x}",
+            "xor {tmp:e}, {tmp:e}",
+            "mov fs, {tmp:x}",
+            "mov gs, {tmp:x}",
+            code = in(reg) KERNEL_CODE_SELECTOR as u64,
+            data = in(reg) KERNEL_DATA_SELECTOR as u64,
+            tmp = out(reg) _,
+        );
+
+        asm!("ltr {0:x}", in(reg) TSS_SELECTOR, options(nostack, preserves_flags));
+    }
+}
+
+// Stack the CPU switches to on entry from ring 3
+pub fn set_kernel_stack(top: u64) {
+    unsafe {
+        let mut rsp = TSS.rsp;
+        rsp[0] = top;
+        TSS.rsp = rsp;
+    }
+}
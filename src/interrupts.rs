@@ -0,0 +1,306 @@
+// --- Interrupt entry stubs, CPU exception handlers and IRQ routing ---
+// Every stub pushes a dummy error code when the CPU does not supply one,
+// followed by the vector number, and jumps to interrupt_common. That saves
+// the general registers so the Rust side receives one InterruptFrame layout
+// for every vector and can inspect or modify the interrupted state.
+
+use core::arch::{asm, naked_asm};
+
+use crate::apic;
+use crate::irq;
+use crate::pic;
+use crate::{set_idt_entry, vga_print, vga_print_hex, vga_print_hex64};
+
+const DUMP_COLOR: u8 = 0x4f;
+
+#[repr(C)]
+pub struct InterruptFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    pub error_code: u64,
+    // Pushed by the CPU
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+#[unsafe(naked)]
+extern "C" fn interrupt_common() {
+    naked_asm!(
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        // The CPU aligned the stack before pushing its 5-word frame; with the
+        // error code, vector and 15 registers rsp is 16-byte aligned again.
+        // Rust code uses SSE, so the interrupted FPU/SSE state is saved too.
+        "sub rsp, 512",
+        "fxsave64 [rsp]",
+        "lea rdi, [rsp + 512]",
+        "cld",
+        "call {dispatch}",
+        "fxrstor64 [rsp]",
+        "add rsp, 512",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        // Drop vector and error code
+        "add rsp, 16",
+        "iretq",
+        dispatch = sym interrupt_dispatch,
+    );
+}
+
+macro_rules! interrupt_stub {
+    ($name:ident, $vector:literal) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            naked_asm!(
+                "push 0",
+                concat!("push ", $vector),
+                "jmp {common}",
+                common = sym interrupt_common,
+            );
+        }
+    };
+    ($name:ident, $vector:literal, error_code) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            naked_asm!(
+                concat!("push ", $vector),
+                "jmp {common}",
+                common = sym interrupt_common,
+            );
+        }
+    };
+}
+
+interrupt_stub!(isr0, 0);
+interrupt_stub!(isr1, 1);
+interrupt_stub!(isr2, 2);
+interrupt_stub!(isr3, 3);
+interrupt_stub!(isr4, 4);
+interrupt_stub!(isr5, 5);
+interrupt_stub!(isr6, 6);
+interrupt_stub!(isr7, 7);
+interrupt_stub!(isr8, 8, error_code);
+interrupt_stub!(isr9, 9);
+interrupt_stub!(isr10, 10, error_code);
+interrupt_stub!(isr11, 11, error_code);
+interrupt_stub!(isr12, 12, error_code);
+interrupt_stub!(isr13, 13, error_code);
+interrupt_stub!(isr14, 14, error_code);
+interrupt_stub!(isr15, 15);
+interrupt_stub!(isr16, 16);
+interrupt_stub!(isr17, 17, error_code);
+interrupt_stub!(isr18, 18);
+interrupt_stub!(isr19, 19);
+interrupt_stub!(isr20, 20);
+interrupt_stub!(isr21, 21, error_code);
+interrupt_stub!(isr22, 22);
+interrupt_stub!(isr23, 23);
+interrupt_stub!(isr24, 24);
+interrupt_stub!(isr25, 25);
+interrupt_stub!(isr26, 26);
+interrupt_stub!(isr27, 27);
+interrupt_stub!(isr28, 28);
+interrupt_stub!(isr29, 29, error_code);
+interrupt_stub!(isr30, 30, error_code);
+interrupt_stub!(isr31, 31);
+
+interrupt_stub!(irq0, 32);
+interrupt_stub!(irq1, 33);
+interrupt_stub!(irq2, 34);
+interrupt_stub!(irq3, 35);
+interrupt_stub!(irq4, 36);
+interrupt_stub!(irq5, 37);
+interrupt_stub!(irq6, 38);
+interrupt_stub!(irq7, 39);
+interrupt_stub!(irq8, 40);
+interrupt_stub!(irq9, 41);
+interrupt_stub!(irq10, 42);
+interrupt_stub!(irq11, 43);
+interrupt_stub!(irq12, 44);
+interrupt_stub!(irq13, 45);
+interrupt_stub!(irq14, 46);
+interrupt_stub!(irq15, 47);
+
+interrupt_stub!(apic_spurious, 255);
+
+const EXCEPTION_STUBS: [extern "C" fn(); 32] = [
+    isr0, isr1, isr2, isr3, isr4, isr5, isr6, isr7,
+    isr8, isr9, isr10, isr11, isr12, isr13, isr14, isr15,
+    isr16, isr17, isr18, isr19, isr20, isr21, isr22, isr23,
+    isr24, isr25, isr26, isr27, isr28, isr29, isr30, isr31,
+];
+
+const IRQ_STUBS: [extern "C" fn(); 16] = [
+    irq0, irq1, irq2, irq3, irq4, irq5, irq6, irq7,
+    irq8, irq9, irq10, irq11, irq12, irq13, irq14, irq15,
+];
+
+const EXCEPTION_NAMES: [&str; 32] = [
+    "Divide Error (#DE)",
+    "Debug (#DB)",
+    "Non-Maskable Interrupt",
+    "Breakpoint (#BP)",
+    "Overflow (#OF)",
+    "Bound Range Exceeded (#BR)",
+    "Invalid Opcode (#UD)",
+    "Device Not Available (#NM)",
+    "Double Fault (#DF)",
+    "Coprocessor Segment Overrun",
+    "Invalid TSS (#TS)",
+    "Segment Not Present (#NP)",
+    "Stack-Segment Fault (#SS)",
+    "General Protection Fault (#GP)",
+    "Page Fault (#PF)",
+    "Reserved",
+    "x87 Floating-Point Exception (#MF)",
+    "Alignment Check (#AC)",
+    "Machine Check (#MC)",
+    "SIMD Floating-Point Exception (#XM)",
+    "Virtualization Exception (#VE)",
+    "Control Protection Exception (#CP)",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor Injection Exception (#HV)",
+    "VMM Communication Exception (#VC)",
+    "Security Exception (#SX)",
+    "Reserved",
+];
+
+const VECTOR_BREAKPOINT: u64 = 3;
+const VECTOR_PAGE_FAULT: u64 = 14;
+
+pub unsafe fn install_exception_handlers() {
+    for (vector, stub) in EXCEPTION_STUBS.iter().enumerate() {
+        set_idt_entry(vector, *stub);
+    }
+}
+
+pub unsafe fn install_irq_handlers() {
+    for (line, stub) in IRQ_STUBS.iter().enumerate() {
+        set_idt_entry(pic::IRQ_BASE as usize + line, *stub);
+    }
+    set_idt_entry(apic::SPURIOUS_VECTOR as usize, apic_spurious);
+}
+
+extern "C" fn interrupt_dispatch(frame: &mut InterruptFrame) {
+    let irq_vectors = pic::IRQ_BASE as u64..(pic::IRQ_BASE + pic::IRQ_COUNT) as u64;
+    if frame.vector < 32 {
+        exception_handler(frame);
+    } else if irq_vectors.contains(&frame.vector) {
+        irq::dispatch(frame);
+    } else if frame.vector == apic::SPURIOUS_VECTOR as u64 {
+        irq::spurious_interrupt();
+    }
+}
+
+fn read_cr2() -> u64 {
+    let value: u64;
+    unsafe { asm!("mov {}, cr2", out(reg) value, options(nomem, nostack, preserves_flags)); }
+    value
+}
+
+fn print_reg(name: &str, value: u64) {
+    vga_print(name, DUMP_COLOR);
+    vga_print_hex64(value, DUMP_COLOR);
+    vga_print("  ", DUMP_COLOR);
+}
+
+fn dump_frame(frame: &InterruptFrame) {
+    vga_print("\nEXCEPTION: ", DUMP_COLOR);
+    vga_print(EXCEPTION_NAMES[frame.vector as usize], DUMP_COLOR);
+    vga_print("  vector ", DUMP_COLOR);
+    vga_print_hex(frame.vector as u32, DUMP_COLOR);
+    vga_print("  error ", DUMP_COLOR);
+    vga_print_hex(frame.error_code as u32, DUMP_COLOR);
+    vga_print("\n", DUMP_COLOR);
+
+    print_reg("RIP ", frame.rip);
+    print_reg("RSP ", frame.rsp);
+    print_reg("RFL ", frame.rflags);
+    vga_print("\n", DUMP_COLOR);
+    print_reg("CS  ", frame.cs);
+    print_reg("SS  ", frame.ss);
+    if frame.vector == VECTOR_PAGE_FAULT {
+        print_reg("CR2 ", read_cr2());
+    }
+    vga_print("\n", DUMP_COLOR);
+    if frame.vector == VECTOR_PAGE_FAULT {
+        vga_print(if frame.error_code & 1 != 0 { "protection violation" } else { "page not present" }, DUMP_COLOR);
+        vga_print(if frame.error_code & 2 != 0 { ", write" } else { ", read" }, DUMP_COLOR);
+        vga_print(if frame.error_code & 4 != 0 { ", user" } else { ", kernel" }, DUMP_COLOR);
+        if frame.error_code & 16 != 0 {
+            vga_print(", instruction fetch", DUMP_COLOR);
+        }
+        vga_print("\n", DUMP_COLOR);
+    }
+
+    let regs = [
+        ("RAX ", frame.rax), ("RBX ", frame.rbx), ("RCX ", frame.rcx),
+        ("RDX ", frame.rdx), ("RSI ", frame.rsi), ("RDI ", frame.rdi),
+        ("RBP ", frame.rbp), ("R8  ", frame.r8), ("R9  ", frame.r9),
+        ("R10 ", frame.r10), ("R11 ", frame.r11), ("R12 ", frame.r12),
+        ("R13 ", frame.r13), ("R14 ", frame.r14), ("R15 ", frame.r15),
+    ];
+    for (i, (name, value)) in regs.iter().enumerate() {
+        print_reg(name, *value);
+        if i % 3 == 2 {
+            vga_print("\n", DUMP_COLOR);
+        }
+    }
+}
+
+fn exception_handler(frame: &mut InterruptFrame) {
+    dump_frame(frame);
+    // int3 is used deliberately; report it and carry on after the instruction
+    if frame.vector == VECTOR_BREAKPOINT {
+        return;
+    }
+    panic!("unhandled CPU exception");
+}
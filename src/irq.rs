@@ -0,0 +1,167 @@
+// --- Hardware IRQ dispatch ---
+// Drivers register one handler per IRQ line. Registering unmasks the line and
+// the dispatcher takes care of spurious interrupts and end-of-interrupt, so a
+// handler only has to service its device. Whether the lines are delivered by
+// the legacy 8259 pair or by the I/O APIC is invisible to drivers.
+
+use crate::acpi::Madt;
+use crate::apic;
+use crate::interrupts::InterruptFrame;
+use crate::pic;
+
+pub type IrqHandler = fn(&mut InterruptFrame);
+
+pub const IRQ_TIMER: u8 = 0;
+pub const IRQ_KEYBOARD: u8 = 1;
+pub const IRQ_COM2: u8 = 3;
+pub const IRQ_COM1: u8 = 4;
+pub const IRQ_MOUSE: u8 = 12;
+
+const IRQ_LINES: usize = pic::IRQ_COUNT as usize;
+
+static mut HANDLERS: [Option<IrqHandler>; IRQ_LINES] = [None; IRQ_LINES];
+static mut IRQ_COUNTS: [u64; IRQ_LINES] = [0; IRQ_LINES];
+static mut SPURIOUS_COUNT: u64 = 0;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Controller {
+    Pic,
+    Apic,
+}
+
+static mut CONTROLLER: Controller = Controller::Pic;
+
+// Start out on the 8259s; the APIC needs paging and ACPI, which come later
+pub fn init() {
+    pic::init();
+}
+
+// Move interrupt delivery from the 8259s to the APIC. Lines that already
+// have handlers are unmasked on the I/O APIC. Returns false (and stays on
+// the PICs) if the APIC could not be set up.
+pub fn use_apic(madt: &Madt) -> bool {
+    without_interrupts(|| {
+        if !apic::init(madt) {
+            return false;
+        }
+        // The PICs stay remapped so a stray IRQ cannot look like an exception
+        pic::disable();
+        unsafe {
+            CONTROLLER = Controller::Apic;
+            for irq in 0..IRQ_LINES as u8 {
+                if HANDLERS[irq as usize].is_some() {
+                    apic::unmask(irq);
+                }
+            }
+        }
+        true
+    })
+}
+
+pub fn controller() -> Controller {
+    unsafe { CONTROLLER }
+}
+
+pub fn enable_interrupts() {
+    unsafe { core::arch::asm!("sti", options(nomem, nostack)); }
+}
+
+pub fn disable_interrupts() {
+    unsafe { core::arch::asm!("cli", options(nomem, nostack)); }
+}
+
+pub fn interrupts_enabled() -> bool {
+    let flags: u64;
+    unsafe { core::arch::asm!("pushfq", "pop {}", out(reg) flags, options(nomem, preserves_flags)); }
+    flags & (1 << 9) != 0
+}
+
+// Run `f` with interrupts disabled, restoring the previous state afterwards
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = interrupts_enabled();
+    disable_interrupts();
+    let result = f();
+    if enabled {
+        enable_interrupts();
+    }
+    result
+}
+
+// Install `handler` for `irq` and unmask the line. Fails if the line is out
+// of range or already owned by another driver.
+pub fn register_handler(irq: u8, handler: IrqHandler) -> bool {
+    if irq as usize >= IRQ_LINES {
+        return false;
+    }
+    let installed = without_interrupts(|| unsafe {
+        if HANDLERS[irq as usize].is_some() {
+            return false;
+        }
+        HANDLERS[irq as usize] = Some(handler);
+        true
+    });
+    if installed {
+        unmask(irq);
+    }
+    installed
+}
+
+pub fn unregister_handler(irq: u8) {
+    if irq as usize >= IRQ_LINES {
+        return;
+    }
+    mask(irq);
+    without_interrupts(|| unsafe { HANDLERS[irq as usize] = None; });
+}
+
+pub fn mask(irq: u8) {
+    match controller() {
+        Controller::Pic => pic::mask(irq),
+        Controller::Apic => apic::mask(irq),
+    }
+}
+
+pub fn unmask(irq: u8) {
+    match controller() {
+        Controller::Pic => pic::unmask(irq),
+        Controller::Apic => apic::unmask(irq),
+    }
+}
+
+fn end_of_interrupt(irq: u8) {
+    match controller() {
+        Controller::Pic => pic::end_of_interrupt(irq),
+        Controller::Apic => apic::end_of_interrupt(),
+    }
+}
+
+// The APIC's spurious vector must not be acknowledged
+pub fn spurious_interrupt() {
+    unsafe { SPURIOUS_COUNT += 1; }
+}
+
+pub fn irq_count(irq: u8) -> u64 {
+    unsafe { IRQ_COUNTS.get(irq as usize).copied().unwrap_or(0) }
+}
+
+pub fn spurious_count() -> u64 {
+    unsafe { SPURIOUS_COUNT }
+}
+
+// Called from the common interrupt entry for vectors IRQ_BASE..IRQ_BASE+16
+pub fn dispatch(frame: &mut InterruptFrame) {
+    let irq = (frame.vector - pic::IRQ_BASE as u64) as u8;
+    // Only the 8259 raises fake IRQ7/15; with the APIC active its output is
+    // cut off at LINT0 and a real IRQ7 must not be mistaken for one
+    if controller() == Controller::Pic && pic::is_spurious(irq) {
+        spurious_interrupt();
+        return;
+    }
+    unsafe {
+        IRQ_COUNTS[irq as usize] += 1;
+        if let Some(handler) = HANDLERS[irq as usize] {
+            handler(frame);
+        }
+    }
+    end_of_interrupt(irq);
+}
@@ -0,0 +1,159 @@
+// --- Interrupt-driven PS/2 keyboard ---
+// IRQ1 moves every scancode from the i8042 data port into a ring buffer, so
+// keys are no longer lost while the kernel is busy (e.g. drawing). Readers
+// either poll the buffer or block in read_key(), which halts the CPU until
+// the next interrupt instead of spinning. read_event() runs the scancodes
+// through the set 1 decoder in keymap and keeps the lock LEDs in sync.
+
+use core::arch::asm;
+
+use crate::interrupts::InterruptFrame;
+use crate::irq;
+use crate::keymap::{Decoder, KeyCode, KeyEvent, Layout};
+use crate::ps2::{self, Device, Port};
+use crate::ringbuf::RingBuffer;
+use crate::vt;
+use crate::inb;
+
+const PS2_DATA: u16 = 0x60;
+const PS2_STATUS: u16 = 0x64;
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const CMD_SET_LEDS: u8 = 0xED;
+
+static SCANCODES: RingBuffer<256> = RingBuffer::new();
+static mut IRQ_ENABLED: bool = false;
+// Only touched by the (single) consumer of SCANCODES
+static mut DECODER: Decoder = Decoder::new();
+
+// Keys read while polling for a command reply
+fn queue_scancode(scancode: u8) {
+    SCANCODES.push(scancode);
+}
+
+fn keyboard_interrupt(_frame: &mut InterruptFrame) {
+    unsafe {
+        // Leave mouse bytes for the IRQ12 handler
+        let status = inb(PS2_STATUS);
+        if status & STATUS_OUTPUT_FULL != 0 && status & STATUS_AUX_DATA == 0 {
+            SCANCODES.push(inb(PS2_DATA));
+        }
+    }
+}
+
+// Requires ps2::init() to have found a keyboard on the first port
+pub fn init() -> bool {
+    if ps2::device(Port::First) != Device::Keyboard {
+        return false;
+    }
+    // Drain anything left over from the firmware, otherwise the controller
+    // keeps its output full and never raises the edge for IRQ1
+    unsafe {
+        while inb(PS2_STATUS) & STATUS_OUTPUT_FULL != 0 {
+            inb(PS2_DATA);
+        }
+    }
+    let ok = irq::register_handler(irq::IRQ_KEYBOARD, keyboard_interrupt);
+    unsafe { IRQ_ENABLED = ok; }
+    ok
+}
+
+pub fn try_read_key() -> Option<u8> {
+    SCANCODES.pop()
+}
+
+// Block until a scancode arrives. Interrupts are disabled around the empty
+// check so a key arriving between the check and `hlt` cannot be slept
+// through: `sti` only takes effect after the following instruction.
+pub fn read_key() -> u8 {
+    loop {
+        if unsafe { !IRQ_ENABLED } {
+            if let Some(sc) = crate::keyboard_poll() {
+                return sc;
+            }
+            continue;
+        }
+        irq::disable_interrupts();
+        if let Some(sc) = SCANCODES.pop() {
+            irq::enable_interrupts();
+            return sc;
+        }
+        unsafe { asm!("sti", "hlt", options(nomem, nostack)); }
+    }
+}
+
+pub fn has_key() -> bool {
+    !SCANCODES.is_empty()
+}
+
+// Scancodes lost because the buffer was full
+pub fn dropped_keys() -> usize {
+    SCANCODES.dropped()
+}
+
+fn decoder() -> &'static mut Decoder {
+    unsafe { &mut *core::ptr::addr_of_mut!(DECODER) }
+}
+
+// Select the layout used to translate keys (keymap::US, UK or DE)
+pub fn set_layout(layout: &'static Layout) {
+    decoder().set_layout(layout);
+}
+
+pub fn layout() -> &'static Layout {
+    decoder().layout()
+}
+
+fn decode(scancode: u8) -> Option<KeyEvent> {
+    let decoder = decoder();
+    let event = decoder.feed(scancode)?;
+    if decoder.take_leds_dirty() {
+        set_leds(decoder.modifiers().led_mask());
+    }
+    // Console hotkeys are handled here and not passed on: Shift+PgUp/PgDn
+    // page through the history, Alt+F1..F6 switch virtual terminals
+    if event.modifiers.shift() && matches!(event.code, KeyCode::PageUp | KeyCode::PageDown) {
+        if event.pressed {
+            vt::scrollback_page(event.code == KeyCode::PageUp);
+        }
+        return None;
+    }
+    if let KeyCode::F(n @ 1..=6) = event.code {
+        if event.modifiers.alt() {
+            if event.pressed {
+                vt::switch_to(n as usize - 1);
+            }
+            return None;
+        }
+    }
+    Some(event)
+}
+
+pub fn try_read_event() -> Option<KeyEvent> {
+    while let Some(sc) = try_read_key() {
+        if let Some(event) = decode(sc) {
+            return Some(event);
+        }
+    }
+    None
+}
+
+// Block until a complete key press or release has been decoded
+pub fn read_event() -> KeyEvent {
+    loop {
+        if let Some(event) = decode(read_key()) {
+            return event;
+        }
+    }
+}
+
+// Light Scroll (bit 0), Num (bit 1) and Caps Lock (bit 2). Interrupts stay
+// off so the ACKs are read here instead of ending up in the scancode queue.
+pub fn set_leds(mask: u8) -> bool {
+    irq::without_interrupts(|| {
+        ps2::send_command_with(Port::First, CMD_SET_LEDS, queue_scancode)
+            .and_then(|_| ps2::send_command_with(Port::First, mask & 0x07, queue_scancode))
+            .is_ok()
+    })
+}
@@ -0,0 +1,401 @@
+// --- Scancode set 1 decoder and keyboard layouts ---
+// Turns the raw byte stream from the keyboard into key events. Physical keys
+// are identified by KeyCode regardless of layout; the active Layout only
+// decides which character a key produces.
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum KeyCode {
+    Escape,
+    // The 47 keys of the main block are named by their scancode, since what
+    // is printed on them depends on the layout
+    Char(u8),
+    Backspace,
+    Tab,
+    Enter,
+    Space,
+    LeftCtrl,
+    RightCtrl,
+    LeftShift,
+    RightShift,
+    LeftAlt,
+    RightAlt,
+    LeftGui,
+    RightGui,
+    Menu,
+    CapsLock,
+    NumLock,
+    ScrollLock,
+    F(u8),
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    PrintScreen,
+    Pause,
+    KeypadDigit(u8),
+    KeypadDecimal,
+    KeypadPlus,
+    KeypadMinus,
+    KeypadMultiply,
+    KeypadDivide,
+    KeypadEnter,
+    Unknown(u16),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub left_alt: bool,
+    // AltGr on European layouts
+    pub right_alt: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    pub fn alt(&self) -> bool {
+        self.left_alt || self.right_alt
+    }
+
+    // Lock state in the bit order the keyboard's Set LEDs command expects
+    pub fn led_mask(&self) -> u8 {
+        (self.scroll_lock as u8) | (self.num_lock as u8) << 1 | (self.caps_lock as u8) << 2
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub pressed: bool,
+    pub modifiers: Modifiers,
+    // Character produced by this key press, if any
+    pub ch: Option<char>,
+}
+
+// Characters of the main block for one layout. Each row string lists the
+// keys from left to right starting at the scancode in ROW_STARTS; '\0' marks
+// a key that produces nothing on that level.
+pub struct Layout {
+    pub name: &'static str,
+    normal: [&'static str; 4],
+    shifted: [&'static str; 4],
+    altgr: [&'static str; 4],
+    // The extra key next to left shift on ISO keyboards (scancode 0x56)
+    iso: [char; 3],
+}
+
+const ROW_STARTS: [u8; 4] = [0x02, 0x10, 0x1E, 0x2B];
+const ROW_LENGTHS: [u8; 4] = [12, 12, 12, 11];
+const ISO_KEY: u8 = 0x56;
+
+pub static US: Layout = Layout {
+    name: "us",
+    normal: ["1234567890-=", "qwertyuiop[]", "asdfghjkl;'`", "\\zxcvbnm,./"],
+    shifted: ["!@#$%^&*()_+", "QWERTYUIOP{}", "ASDFGHJKL:\"~", "|ZXCVBNM<>?"],
+    altgr: ["", "", "", ""],
+    iso: ['\\', '|', '\0'],
+};
+
+pub static UK: Layout = Layout {
+    name: "uk",
+    normal: ["1234567890-=", "qwertyuiop[]", "asdfghjkl;'`", "#zxcvbnm,./"],
+    shifted: ["!\"£$%^&*()_+", "QWERTYUIOP{}", "ASDFGHJKL:@¬", "~ZXCVBNM<>?"],
+    altgr: ["\0\0\0€", "\0\0é\0\0\0ú\0ó", "á\0\0\0\0\0\0\0\0\0\0¦", ""],
+    iso: ['\\', '|', '\0'],
+};
+
+pub static DE: Layout = Layout {
+    name: "de",
+    normal: ["1234567890ß´", "qwertzuiopü+", "asdfghjklöä^", "#yxcvbnm,.-"],
+    shifted: ["!\"§$%&/()=?`", "QWERTZUIOPÜ*", "ASDFGHJKLÖÄ°", "'YXCVBNM;:_"],
+    altgr: ["\0²³\0\0\0{[]}\\\0", "@\0€\0\0\0\0\0\0\0\0~", "", "\0\0\0\0\0\0\0µ"],
+    iso: ['<', '>', '|'],
+};
+
+pub static LAYOUTS: [&Layout; 3] = [&US, &UK, &DE];
+
+pub fn find_layout(name: &str) -> Option<&'static Layout> {
+    LAYOUTS.iter().copied().find(|layout| layout.name == name)
+}
+
+impl Layout {
+    fn lookup(&self, scancode: u8, level: usize) -> Option<char> {
+        if scancode == ISO_KEY {
+            return Some(self.iso[level]).filter(|&c| c != '\0');
+        }
+        let row = (0..4).find(|&r| {
+            scancode >= ROW_STARTS[r] && scancode < ROW_STARTS[r] + ROW_LENGTHS[r]
+        })?;
+        let table = match level {
+            0 => self.normal[row],
+            1 => self.shifted[row],
+            _ => self.altgr[row],
+        };
+        table
+            .chars()
+            .nth((scancode - ROW_STARTS[row]) as usize)
+            .filter(|&c| c != '\0')
+    }
+
+    // Character for a main-block key under the given modifiers
+    pub fn translate(&self, scancode: u8, modifiers: &Modifiers) -> Option<char> {
+        if modifiers.right_alt {
+            return self.lookup(scancode, 2);
+        }
+        let normal = self.lookup(scancode, 0)?;
+        // Caps Lock only affects letters, and Shift reverses it
+        let shift = modifiers.shift() ^ (modifiers.caps_lock && normal.is_alphabetic());
+        if shift { self.lookup(scancode, 1) } else { Some(normal) }
+    }
+}
+
+fn is_main_block(scancode: u8) -> bool {
+    scancode == ISO_KEY
+        || (0..4).any(|r| scancode >= ROW_STARTS[r] && scancode < ROW_STARTS[r] + ROW_LENGTHS[r])
+}
+
+fn keycode(scancode: u8, extended: bool) -> KeyCode {
+    if extended {
+        return match scancode {
+            0x1C => KeyCode::KeypadEnter,
+            0x1D => KeyCode::RightCtrl,
+            0x35 => KeyCode::KeypadDivide,
+            0x37 => KeyCode::PrintScreen,
+            0x38 => KeyCode::RightAlt,
+            0x47 => KeyCode::Home,
+            0x48 => KeyCode::Up,
+            0x49 => KeyCode::PageUp,
+            0x4B => KeyCode::Left,
+            0x4D => KeyCode::Right,
+            0x4F => KeyCode::End,
+            0x50 => KeyCode::Down,
+            0x51 => KeyCode::PageDown,
+            0x52 => KeyCode::Insert,
+            0x53 => KeyCode::Delete,
+            0x5B => KeyCode::LeftGui,
+            0x5C => KeyCode::RightGui,
+            0x5D => KeyCode::Menu,
+            sc => KeyCode::Unknown(0xE000 | sc as u16),
+        };
+    }
+    match scancode {
+        0x01 => KeyCode::Escape,
+        0x0E => KeyCode::Backspace,
+        0x0F => KeyCode::Tab,
+        0x1C => KeyCode::Enter,
+        0x1D => KeyCode::LeftCtrl,
+        0x2A => KeyCode::LeftShift,
+        0x36 => KeyCode::RightShift,
+        0x37 => KeyCode::KeypadMultiply,
+        0x38 => KeyCode::LeftAlt,
+        0x39 => KeyCode::Space,
+        0x3A => KeyCode::CapsLock,
+        0x3B..=0x44 => KeyCode::F(scancode - 0x3B + 1),
+        0x45 => KeyCode::NumLock,
+        0x46 => KeyCode::ScrollLock,
+        0x47 => KeyCode::KeypadDigit(7),
+        0x48 => KeyCode::KeypadDigit(8),
+        0x49 => KeyCode::KeypadDigit(9),
+        0x4A => KeyCode::KeypadMinus,
+        0x4B => KeyCode::KeypadDigit(4),
+        0x4C => KeyCode::KeypadDigit(5),
+        0x4D => KeyCode::KeypadDigit(6),
+        0x4E => KeyCode::KeypadPlus,
+        0x4F => KeyCode::KeypadDigit(1),
+        0x50 => KeyCode::KeypadDigit(2),
+        0x51 => KeyCode::KeypadDigit(3),
+        0x52 => KeyCode::KeypadDigit(0),
+        0x53 => KeyCode::KeypadDecimal,
+        0x57 => KeyCode::F(11),
+        0x58 => KeyCode::F(12),
+        sc if is_main_block(sc) => KeyCode::Char(sc),
+        sc => KeyCode::Unknown(sc as u16),
+    }
+}
+
+// Keypad keys act as navigation keys when Num Lock is off (or Shift is held)
+fn keypad_navigation(code: KeyCode) -> KeyCode {
+    match code {
+        KeyCode::KeypadDigit(7) => KeyCode::Home,
+        KeyCode::KeypadDigit(8) => KeyCode::Up,
+        KeyCode::KeypadDigit(9) => KeyCode::PageUp,
+        KeyCode::KeypadDigit(4) => KeyCode::Left,
+        KeyCode::KeypadDigit(6) => KeyCode::Right,
+        KeyCode::KeypadDigit(1) => KeyCode::End,
+        KeyCode::KeypadDigit(2) => KeyCode::Down,
+        KeyCode::KeypadDigit(3) => KeyCode::PageDown,
+        KeyCode::KeypadDigit(0) => KeyCode::Insert,
+        KeyCode::KeypadDecimal => KeyCode::Delete,
+        other => other,
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum PrefixState {
+    None,
+    Extended,
+    // Pause sends E1 1D 45 E1 9D C5; count the bytes still to swallow
+    Pause(u8),
+}
+
+pub struct Decoder {
+    state: PrefixState,
+    modifiers: Modifiers,
+    layout: &'static Layout,
+    // Set when a lock key changed and the LEDs need updating
+    leds_dirty: bool,
+}
+
+impl Decoder {
+    pub const fn new() -> Decoder {
+        Decoder {
+            state: PrefixState::None,
+            modifiers: Modifiers {
+                left_shift: false,
+                right_shift: false,
+                left_ctrl: false,
+                right_ctrl: false,
+                left_alt: false,
+                right_alt: false,
+                caps_lock: false,
+                num_lock: false,
+                scroll_lock: false,
+            },
+            layout: &US,
+            leds_dirty: false,
+        }
+    }
+
+    pub fn set_layout(&mut self, layout: &'static Layout) {
+        self.layout = layout;
+    }
+
+    pub fn layout(&self) -> &'static Layout {
+        self.layout
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    // Returns true once after a lock key toggled
+    pub fn take_leds_dirty(&mut self) -> bool {
+        core::mem::replace(&mut self.leds_dirty, false)
+    }
+
+    // Feed one byte from the keyboard; returns an event once a complete
+    // make or break code has been seen
+    pub fn feed(&mut self, byte: u8) -> Option<KeyEvent> {
+        match self.state {
+            PrefixState::Pause(remaining) => {
+                self.state = if remaining > 1 { PrefixState::Pause(remaining - 1) } else { PrefixState::None };
+                if remaining == 1 {
+                    return Some(self.event(KeyCode::Pause, true));
+                }
+                return None;
+            }
+            PrefixState::None if byte == 0xE0 => {
+                self.state = PrefixState::Extended;
+                return None;
+            }
+            PrefixState::None if byte == 0xE1 => {
+                self.state = PrefixState::Pause(5);
+                return None;
+            }
+            _ => {}
+        }
+        // Keyboard replies (ACK, resend) can show up in the stream
+        if byte == 0xFA || byte == 0xFE {
+            self.state = PrefixState::None;
+            return None;
+        }
+
+        let extended = self.state == PrefixState::Extended;
+        self.state = PrefixState::None;
+        let pressed = byte & 0x80 == 0;
+        let scancode = byte & 0x7F;
+        // E0 2A / E0 AA are fake shifts sent around some extended keys
+        if extended && (scancode == 0x2A || scancode == 0x36) {
+            return None;
+        }
+
+        let mut code = keycode(scancode, extended);
+        self.update_modifiers(code, pressed);
+        if let KeyCode::KeypadDigit(_) | KeyCode::KeypadDecimal = code {
+            if !self.modifiers.num_lock || self.modifiers.shift() {
+                code = keypad_navigation(code);
+            }
+        }
+
+        let mut event = self.event(code, pressed);
+        if pressed {
+            event.ch = self.character(code, scancode);
+        }
+        Some(event)
+    }
+
+    fn event(&self, code: KeyCode, pressed: bool) -> KeyEvent {
+        KeyEvent { code, pressed, modifiers: self.modifiers, ch: None }
+    }
+
+    fn update_modifiers(&mut self, code: KeyCode, pressed: bool) {
+        let m = &mut self.modifiers;
+        match code {
+            KeyCode::LeftShift => m.left_shift = pressed,
+            KeyCode::RightShift => m.right_shift = pressed,
+            KeyCode::LeftCtrl => m.left_ctrl = pressed,
+            KeyCode::RightCtrl => m.right_ctrl = pressed,
+            KeyCode::LeftAlt => m.left_alt = pressed,
+            KeyCode::RightAlt => m.right_alt = pressed,
+            KeyCode::CapsLock if pressed => {
+                m.caps_lock = !m.caps_lock;
+                self.leds_dirty = true;
+            }
+            KeyCode::NumLock if pressed => {
+                m.num_lock = !m.num_lock;
+                self.leds_dirty = true;
+            }
+            KeyCode::ScrollLock if pressed => {
+                m.scroll_lock = !m.scroll_lock;
+                self.leds_dirty = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn character(&self, code: KeyCode, scancode: u8) -> Option<char> {
+        match code {
+            KeyCode::Char(_) => self.layout.translate(scancode, &self.modifiers),
+            KeyCode::Space => Some(' '),
+            KeyCode::Enter | KeyCode::KeypadEnter => Some('\n'),
+            KeyCode::Tab => Some('\t'),
+            KeyCode::Backspace => Some('\x08'),
+            KeyCode::Escape => Some('\x1b'),
+            KeyCode::KeypadDigit(d) => char::from_digit(d as u32, 10),
+            KeyCode::KeypadDecimal => Some('.'),
+            KeyCode::KeypadPlus => Some('+'),
+            KeyCode::KeypadMinus => Some('-'),
+            KeyCode::KeypadMultiply => Some('*'),
+            KeyCode::KeypadDivide => Some('/'),
+            _ => None,
+        }
+    }
+}
@@ -6,80 +6,59 @@
 use core::arch::asm;
 use core::panic::PanicInfo;
 
-// --- VGA text mode constants and statics ---
-const BUFFER_WIDTH: usize = 80;
-const BUFFER_HEIGHT: usize = 25;
-static mut VGA_BUFFER: *mut u8 = 0xb8000 as *mut u8;
-static mut CURSOR_ROW: usize = 0;
-static mut CURSOR_COL: usize = 0;
-
-// --- VGA text mode functions ---
+mod acpi;
+mod apic;
+mod bga;
+#[macro_use]
+mod console;
+mod fbcon;
+mod gdt;
+mod interrupts;
+mod irq;
+mod keyboard;
+mod keymap;
+#[macro_use]
+mod log;
+mod memory;
+mod mouse;
+mod multiboot;
+mod modex;
+mod paging;
+mod palette;
+mod pci;
+mod pic;
+mod planar;
+mod ps2;
+mod ringbuf;
+mod serial;
+mod timer;
+mod vga;
+mod vt;
+
+// --- VGA text console ---
+// The text screen is managed by vt; kernel output goes to its log console,
+// which is what these helpers write to. While a graphics mode is active the
+// output is also drawn by fbcon, so it stays visible; the log console keeps
+// a copy for when text mode comes back.
 fn vga_clear() {
-    unsafe {
-        for row in 0..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let offset = row * BUFFER_WIDTH * 2 + col * 2;
-                *VGA_BUFFER.add(offset) = b' ';
-                *VGA_BUFFER.add(offset + 1) = 0x2f;
-            }
-        }
-        CURSOR_ROW = 0;
-        CURSOR_COL = 0;
-    }
+    vt::clear(vt::LOG_CONSOLE);
 }
 
-fn vga_print_at(s: &str, mut row: usize, mut col: usize, color: u8) {
-    unsafe {
-        for byte in s.bytes() {
-            match byte {
-                b'\n' => {
-                    row += 1;
-                    col = 0;
-                }
-                b'\r' => col = 0,
-                b => {
-                    if row >= BUFFER_HEIGHT {
-                        vga_scroll();
-                        row = BUFFER_HEIGHT - 1;
-                    }
-                    let offset = row * BUFFER_WIDTH * 2 + col * 2;
-                    *VGA_BUFFER.add(offset) = b;
-                    *VGA_BUFFER.add(offset + 1) = color;
-                    col += 1;
-                    if col >= BUFFER_WIDTH {
-                        row += 1;
-                        col = 0;
-                    }
-                }
-            }
-        }
-        CURSOR_ROW = row;
-        CURSOR_COL = col;
-    }
+fn vga_print_at(s: &str, row: usize, col: usize, color: u8) {
+    vt::set_cursor(vt::LOG_CONSOLE, row, col);
+    vga_write(s.as_bytes(), color);
 }
 
-fn vga_scroll() {
-    unsafe {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let from = row * BUFFER_WIDTH * 2 + col * 2;
-                let to = (row - 1) * BUFFER_WIDTH * 2 + col * 2;
-                *VGA_BUFFER.add(to) = *VGA_BUFFER.add(from);
-                *VGA_BUFFER.add(to + 1) = *VGA_BUFFER.add(from + 1);
-            }
-        }
-        for col in 0..BUFFER_WIDTH {
-            let offset = (BUFFER_HEIGHT - 1) * BUFFER_WIDTH * 2 + col * 2;
-            *VGA_BUFFER.add(offset) = b' ';
-            *VGA_BUFFER.add(offset + 1) = 0x2f;
-        }
-    }
+// Everything printed on the console is mirrored to the serial port
+fn vga_print(s: &str, color: u8) {
+    serial::write_str(s);
+    vga_write(s.as_bytes(), color);
 }
 
-fn vga_print(s: &str, color: u8) {
-    unsafe {
-        vga_print_at(s, CURSOR_ROW, CURSOR_COL, color);
-    }
+// Write raw code page 437 bytes, so characters outside ASCII can be shown
+fn vga_write(bytes: &[u8], color: u8) {
+    vt::write(vt::LOG_CONSOLE, bytes, color);
+    fbcon::write(bytes, color);
 }
 
 fn vga_print_hex(num: u32, color: u8) {
@@ -94,6 +73,17 @@ fn vga_print_hex(num: u32, color: u8) {
     vga_print(s, color);
 }
 
+fn vga_print_hex64(num: u64, color: u8) {
+    const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    let mut buf = [b'0'; 18];
+    buf[1] = b'x';
+    for i in 0..16 {
+        buf[2 + i] = HEX_DIGITS[((num >> (60 - i * 4)) & 0xF) as usize];
+    }
+    let s = core::str::from_utf8(&buf).unwrap_or("");
+    vga_print(s, color);
+}
+
 // --- Simple RAM-based file system ---
 const MAX_FILES: usize = 4;
 const MAX_FILE_SIZE: usize = 256;
@@ -162,9 +152,8 @@ fn file_find(name: &str) -> Option<usize> {
 
 // --- Enhanced Graphics System ---
 const FB_ADDR: *mut u8 = 0xA0000 as *mut u8;
-const FB_WIDTH: usize = 320;
-const FB_HEIGHT: usize = 200;
-const FB_SIZE: usize = FB_WIDTH * FB_HEIGHT;
+// Largest screen the back buffer holds (Mode 13h)
+const FB_SIZE: usize = 320 * 200;
 
 // Double buffering - back buffer in memory
 static mut BACK_BUFFER: [u8; FB_SIZE] = [0; FB_SIZE];
@@ -179,13 +168,76 @@ struct VideoMode {
     mode_id: u8,
 }
 
-const VIDEO_MODES: [VideoMode; 3] = [
+const VIDEO_MODES: [VideoMode; 5] = [
     VideoMode { width: 320, height: 200, bpp: 8, mode_id: 0x13 }, // Mode 13h
     VideoMode { width: 640, height: 480, bpp: 1, mode_id: 0x12 }, // Mode 12h (VGA)
     VideoMode { width: 80, height: 25, bpp: 4, mode_id: 0x03 },   // Text mode
+    VideoMode { width: 320, height: 240, bpp: 8, mode_id: MODE_X_ID }, // Mode X (unchained)
+    VideoMode { width: 1024, height: 768, bpp: 32, mode_id: BGA_MODE_ID }, // Bochs/QEMU adapter
 ];
 
-static mut CURRENT_MODE: VideoMode = VideoMode { width: 320, height: 200, bpp: 8, mode_id: 0x13 };
+const TEXT_MODE_ID: u8 = 0x03;
+const PLANAR_MODE_ID: u8 = 0x12;
+// Mode X has no BIOS mode number; this one is ours
+const MODE_X_ID: u8 = 0x80;
+// Any resolution on the Bochs graphics adapter (width and height come from
+// the VideoMode), also ours
+const BGA_MODE_ID: u8 = 0x81;
+
+// The boot loader leaves us in text mode
+static mut CURRENT_MODE: VideoMode = VIDEO_MODES[2];
+
+// Size of the drawable screen in the current mode. Text mode has none, so
+// every drawing call is clipped away there.
+fn fb_width() -> usize {
+    unsafe { if CURRENT_MODE.mode_id == TEXT_MODE_ID { 0 } else { CURRENT_MODE.width } }
+}
+
+fn fb_height() -> usize {
+    unsafe { if CURRENT_MODE.mode_id == TEXT_MODE_ID { 0 } else { CURRENT_MODE.height } }
+}
+
+// How the current mode lays out pixels, which decides the code drawing them
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Backend {
+    Text,   // nothing to draw on
+    Linear, // one byte per pixel at FB_ADDR (Mode 13h)
+    Planar, // four bit planes (Mode 12h), see planar.rs
+    ModeX,  // unchained pages, see modex.rs
+    Bga,    // 32 bpp linear framebuffer, see bga.rs
+}
+
+fn fb_backend() -> Backend {
+    match unsafe { CURRENT_MODE.mode_id } {
+        TEXT_MODE_ID => Backend::Text,
+        PLANAR_MODE_ID => Backend::Planar,
+        MODE_X_ID => Backend::ModeX,
+        BGA_MODE_ID => Backend::Bga,
+        _ => Backend::Linear,
+    }
+}
+
+// Helpers shared by the drawing backends
+
+// Part of a `w` x `h` area at (x, y) that is on screen, as (w, h)
+fn clip_area(x: usize, y: usize, w: usize, h: usize) -> (usize, usize) {
+    (w.min(fb_width().saturating_sub(x)), h.min(fb_height().saturating_sub(y)))
+}
+
+// Whether pixel (col, row) of a 1bpp bitmap `w` pixels wide is set. Rows are
+// padded to whole bytes with the leftmost pixel in the top bit; bytes past
+// the end of `bitmap` read as clear.
+fn bitmap_bit(bitmap: &[u8], w: usize, col: usize, row: usize) -> bool {
+    let byte = bitmap.get(row * w.div_ceil(8) + col / 8).copied().unwrap_or(0);
+    byte & (0x80 >> (col % 8)) != 0
+}
+
+// Order for copying an area onto an overlapping one: walking away from the
+// destination means no source pixel is overwritten before it is read.
+// Returns (rows bottom-up, columns right to left).
+fn copy_direction(src_x: usize, src_y: usize, dst_x: usize, dst_y: usize) -> (bool, bool) {
+    (dst_y > src_y, dst_y == src_y && dst_x > src_x)
+}
 
 // Sprite structure for better sprite handling
 #[derive(Copy, Clone)]
@@ -205,6 +257,9 @@ struct AnimationFrame<'a> {
 // Simple timer for animations (frame counter)
 static mut FRAME_COUNTER: u32 = 0;
 
+// Wait for vertical retrace before copying the back buffer to the screen
+static mut VSYNC_ENABLED: bool = true;
+
 const VGA_MISC_WRITE: u16 = 0x3C2;
 const VGA_CRTC_INDEX: u16 = 0x3D4;
 const VGA_CRTC_DATA: u16 = 0x3D5;
@@ -212,46 +267,70 @@ const VGA_SEQ_INDEX: u16 = 0x3C4;
 const VGA_SEQ_DATA: u16 = 0x3C5;
 const VGA_GC_INDEX: u16 = 0x3CE;
 const VGA_GC_DATA: u16 = 0x3CF;
+const VGA_AC_INDEX: u16 = 0x3C0;
+const VGA_INSTAT_READ: u16 = 0x3DA;
 
 fn init_graphics_mode() {
-    unsafe {
-        asm!("cli");
-        outb(VGA_MISC_WRITE, 0x63);
-        outb(VGA_SEQ_INDEX, 0x00); outb(VGA_SEQ_DATA, 0x03);
-        outb(VGA_SEQ_INDEX, 0x01); outb(VGA_SEQ_DATA, 0x01);
-        outb(VGA_SEQ_INDEX, 0x02); outb(VGA_SEQ_DATA, 0x0F);
-        outb(VGA_SEQ_INDEX, 0x03); outb(VGA_SEQ_DATA, 0x00);
-        outb(VGA_SEQ_INDEX, 0x04); outb(VGA_SEQ_DATA, 0x0E);
-        outb(VGA_CRTC_INDEX, 0x11); outb(VGA_CRTC_DATA, 0x0E);
-        outb(VGA_CRTC_INDEX, 0x00); outb(VGA_CRTC_DATA, 0x5F);
-        outb(VGA_CRTC_INDEX, 0x01); outb(VGA_CRTC_DATA, 0x4F);
-        outb(VGA_CRTC_INDEX, 0x02); outb(VGA_CRTC_DATA, 0x50);
-        outb(VGA_CRTC_INDEX, 0x03); outb(VGA_CRTC_DATA, 0x82);
-        outb(VGA_CRTC_INDEX, 0x04); outb(VGA_CRTC_DATA, 0x54);
-        outb(VGA_CRTC_INDEX, 0x05); outb(VGA_CRTC_DATA, 0x80);
-        outb(VGA_CRTC_INDEX, 0x06); outb(VGA_CRTC_DATA, 0xBF);
-        outb(VGA_CRTC_INDEX, 0x07); outb(VGA_CRTC_DATA, 0x1F);
-        outb(VGA_CRTC_INDEX, 0x08); outb(VGA_CRTC_DATA, 0x00);
-        outb(VGA_CRTC_INDEX, 0x09); outb(VGA_CRTC_DATA, 0x41);
-        outb(VGA_CRTC_INDEX, 0x10); outb(VGA_CRTC_DATA, 0x9C);
-        outb(VGA_CRTC_INDEX, 0x11); outb(VGA_CRTC_DATA, 0x8E);
-        outb(VGA_CRTC_INDEX, 0x12); outb(VGA_CRTC_DATA, 0x8F);
-        outb(VGA_CRTC_INDEX, 0x13); outb(VGA_CRTC_DATA, 0x28);
-        outb(VGA_CRTC_INDEX, 0x14); outb(VGA_CRTC_DATA, 0x40);
-        outb(VGA_CRTC_INDEX, 0x15); outb(VGA_CRTC_DATA, 0x96);
-        outb(VGA_CRTC_INDEX, 0x16); outb(VGA_CRTC_DATA, 0xB9);
-        outb(VGA_CRTC_INDEX, 0x17); outb(VGA_CRTC_DATA, 0xA3);
-        outb(VGA_GC_INDEX, 0x00); outb(VGA_GC_DATA, 0x00);
-        outb(VGA_GC_INDEX, 0x01); outb(VGA_GC_DATA, 0x00);
-        outb(VGA_GC_INDEX, 0x02); outb(VGA_GC_DATA, 0x00);
-        outb(VGA_GC_INDEX, 0x03); outb(VGA_GC_DATA, 0x00);
-        outb(VGA_GC_INDEX, 0x04); outb(VGA_GC_DATA, 0x00);
-        outb(VGA_GC_INDEX, 0x05); outb(VGA_GC_DATA, 0x40);
-        outb(VGA_GC_INDEX, 0x06); outb(VGA_GC_DATA, 0x05);
-        outb(VGA_GC_INDEX, 0x07); outb(VGA_GC_DATA, 0x0F);
-        outb(VGA_GC_INDEX, 0x08); outb(VGA_GC_DATA, 0xFF);
-        asm!("sti");
+    set_video_mode(VIDEO_MODES[0]);
+}
+
+// Switch to `mode` (one of VIDEO_MODES) by loading its full VGA register
+// set, or for BGA_MODE_ID by programming the Bochs adapter. Text mode gets
+// its font and the active terminal back; graphics modes start out cleared,
+// with console output going to fbcon. Returns false for a mode we have no
+// registers for or the adapter can't show.
+fn set_video_mode(mode: VideoMode) -> bool {
+    let regs = vga::registers_for(mode.mode_id);
+    if mode.mode_id == BGA_MODE_ID {
+        if let Err(err) = bga::check_mode(mode.width, mode.height, mode.bpp) {
+            warn!("{}x{}x{}: {}", mode.width, mode.height, mode.bpp, err.description());
+            return false;
+        }
+    } else if regs.is_none() {
+        return false;
     }
+    irq::without_interrupts(|| unsafe {
+        // Buffers and cursor belong to the old screen layout
+        fb_cursor_hide();
+        DOUBLE_BUFFER_ENABLED = false;
+        fbcon::deactivate();
+        if CURRENT_MODE.mode_id == TEXT_MODE_ID {
+            vt::suspend();
+            vga::save_font();
+        }
+        let mut height = mode.height;
+        match regs {
+            Some(regs) => {
+                if CURRENT_MODE.mode_id == BGA_MODE_ID {
+                    bga::disable();
+                }
+                vga::write_registers(regs);
+            }
+            // The adapter draws from the same video memory as the VGA,
+            // font plane included, which is why the font is saved above
+            // The adapter may leave out rows (see bga::set_mode); the mode
+            // records the ones that can be drawn, which every clip goes by
+            None => height = bga::set_mode(mode.width, mode.height),
+        }
+        CURRENT_MODE = VideoMode { height, ..mode };
+        if mode.mode_id == TEXT_MODE_ID {
+            vga::restore_font();
+            vt::resume();
+        } else {
+            // The adapter clears its screen itself
+            if mode.mode_id != BGA_MODE_ID {
+                vga::clear_graphics_memory();
+            }
+            if mode.mode_id == MODE_X_ID {
+                modex::reset();
+            }
+            MOUSE_CURSOR_X = fb_width() / 2;
+            MOUSE_CURSOR_Y = fb_height() / 2;
+            // The text screen is gone; keep console output visible
+            fbcon::activate();
+        }
+    });
+    true
 }
 
 #[inline]
@@ -259,23 +338,90 @@ unsafe fn outb(port: u16, val: u8) {
     asm!("out dx, al", in("dx") port, in("al") val);
 }
 
+#[inline]
+unsafe fn inb(port: u16) -> u8 {
+    let val: u8;
+    asm!("in al, dx", in("dx") port, out("al") val);
+    val
+}
+
+#[inline]
+unsafe fn outw(port: u16, val: u16) {
+    asm!("out dx, ax", in("dx") port, in("ax") val);
+}
+
+#[inline]
+unsafe fn inw(port: u16) -> u16 {
+    let val: u16;
+    asm!("in ax, dx", in("dx") port, out("ax") val);
+    val
+}
+
+#[inline]
+unsafe fn outl(port: u16, val: u32) {
+    asm!("out dx, eax", in("dx") port, in("eax") val);
+}
+
+#[inline]
+unsafe fn inl(port: u16) -> u32 {
+    let val: u32;
+    asm!("in eax, dx", in("dx") port, out("eax") val);
+    val
+}
+
+// Give slow devices such as the 8259 time to settle between writes
+#[inline]
+unsafe fn io_wait() {
+    outb(0x80, 0);
+}
+
+#[inline]
+unsafe fn rdmsr(msr: u32) -> u64 {
+    let (lo, hi): (u32, u32);
+    asm!("rdmsr", in("ecx") msr, out("eax") lo, out("edx") hi, options(nomem, nostack, preserves_flags));
+    ((hi as u64) << 32) | lo as u64
+}
+
+#[inline]
+unsafe fn wrmsr(msr: u32, val: u64) {
+    asm!("wrmsr", in("ecx") msr, in("eax") val as u32, in("edx") (val >> 32) as u32, options(nostack, preserves_flags));
+}
+
 fn fb_clear(color: u8) {
+    match fb_backend() {
+        Backend::Planar => return planar::clear(color),
+        Backend::ModeX => return modex::clear(modex::visible_page(), color),
+        Backend::Bga => return bga::clear(color),
+        _ => {}
+    }
     unsafe {
-        for i in 0..(FB_WIDTH * FB_HEIGHT) {
+        for i in 0..fb_width() * fb_height() {
             *FB_ADDR.add(i) = color;
         }
     }
 }
 
 fn fb_set_pixel(x: usize, y: usize, color: u8) {
-    if x < FB_WIDTH && y < FB_HEIGHT {
+    match fb_backend() {
+        Backend::Planar => return planar::set_pixel(x, y, color),
+        Backend::ModeX => return modex::set_pixel(modex::visible_page(), x, y, color),
+        Backend::Bga => return bga::set_pixel(x, y, color),
+        _ => {}
+    }
+    if x < fb_width() && y < fb_height() {
         unsafe {
-            *FB_ADDR.add(y * FB_WIDTH + x) = color;
+            *FB_ADDR.add(y * fb_width() + x) = color;
         }
     }
 }
 
 fn fb_draw_rect(x: usize, y: usize, w: usize, h: usize, color: u8) {
+    match fb_backend() {
+        Backend::Planar => return planar::fill_rect(x, y, w, h, color),
+        Backend::ModeX => return modex::fill_rect(modex::visible_page(), x, y, w, h, color),
+        Backend::Bga => return bga::fill_rect(x, y, w, h, color),
+        _ => {}
+    }
     for dy in 0..h {
         for dx in 0..w {
             fb_set_pixel(x + dx, y + dy, color);
@@ -289,7 +435,7 @@ fn fb_draw_line(mut x0: isize, mut y0: isize, x1: isize, y1: isize, color: u8) {
     let dy = -(y1 - y0).abs();
     let sy = if y0 < y1 { 1 } else { -1 };
     let mut err = dx + dy;
-    let (w, h) = (FB_WIDTH as isize, FB_HEIGHT as isize);
+    let (w, h) = (fb_width() as isize, fb_height() as isize);
     loop {
         if x0 >= 0 && x0 < w && y0 >= 0 && y0 < h {
             fb_set_pixel(x0 as usize, y0 as usize, color);
@@ -308,7 +454,7 @@ fn fb_draw_line(mut x0: isize, mut y0: isize, x1: isize, y1: isize, color: u8) {
 }
 
 fn fb_draw_circle(cx: usize, cy: usize, radius: usize, color: u8) {
-    let (w, h) = (FB_WIDTH as isize, FB_HEIGHT as isize);
+    let (w, h) = (fb_width() as isize, fb_height() as isize);
     let (mut x, mut y) = (radius as isize, 0isize);
     let mut err = 0isize;
     let cx = cx as isize;
@@ -334,11 +480,15 @@ fn fb_draw_circle(cx: usize, cy: usize, radius: usize, color: u8) {
 }
 
 fn fb_blit_bitmap(x: usize, y: usize, w: usize, h: usize, bitmap: &[u8], color: u8) {
+    match fb_backend() {
+        Backend::Planar => return planar::draw_bitmap(x, y, w, h, bitmap, color),
+        Backend::ModeX => return modex::draw_bitmap(modex::visible_page(), x, y, w, h, bitmap, color),
+        Backend::Bga => return bga::draw_bitmap(x, y, w, h, bitmap, color),
+        _ => {}
+    }
     for row in 0..h {
         for col in 0..w {
-            let byte_idx = (row * ((w + 7) / 8)) + (col / 8);
-            let bit = 7 - (col % 8);
-            if byte_idx < bitmap.len() && (bitmap[byte_idx] & (1 << bit)) != 0 {
+            if bitmap_bit(bitmap, w, col, row) {
                 fb_set_pixel(x + col, y + row, color);
             }
         }
@@ -447,10 +597,18 @@ static FONT_OK: [[u8; 8]; 2] = [
 
 // --- Enhanced Graphics Functions ---
 
-// Enable or disable double buffering
+// Enable or disable double buffering. Mode X draws on its hidden page
+// instead of the back buffer; other screens larger than the back buffer are
+// always drawn directly.
 fn fb_enable_double_buffer(enable: bool) {
     unsafe {
-        DOUBLE_BUFFER_ENABLED = enable;
+        if fb_backend() == Backend::ModeX {
+            DOUBLE_BUFFER_ENABLED = enable;
+            let shown = modex::visible_page();
+            modex::set_draw_page(if enable { 1 - shown.min(1) } else { shown });
+            return;
+        }
+        DOUBLE_BUFFER_ENABLED = enable && fb_width() * fb_height() <= FB_SIZE;
         if enable {
             // Clear back buffer
             for i in 0..FB_SIZE {
@@ -471,23 +629,69 @@ fn get_draw_buffer() -> *mut u8 {
     }
 }
 
-// Swap buffers (copy back buffer to screen)
+// Sync buffer swaps to vertical retrace (on by default). Without it the
+// copy can land while the screen is being drawn, showing parts of two
+// frames. In Mode X it picks a page flip over a copy to the shown page.
+fn fb_set_vsync(enable: bool) {
+    unsafe { VSYNC_ENABLED = enable; }
+}
+
+// Swap buffers (copy back buffer to screen). Afterwards the back buffer
+// still holds the frame just shown, so drawing can continue on top of it.
 fn fb_swap_buffers() {
+    fb_present(unsafe { VSYNC_ENABLED });
+}
+
+// Show the back buffer, waiting for vertical retrace first if `vsync`.
+// Without it nothing blocks, which callers running with interrupts off
+// (fbcon) need; Mode X then copies the frame onto the shown page instead of
+// flipping to it.
+fn fb_present(vsync: bool) {
     unsafe {
-        if DOUBLE_BUFFER_ENABLED {
-            // Fast memory copy from back buffer to screen
-            for i in 0..FB_SIZE {
+        if DOUBLE_BUFFER_ENABLED && fb_backend() == Backend::ModeX {
+            let cursor = MOUSE_CURSOR_VISIBLE;
+            fb_cursor_hide();
+            if vsync {
+                // Flip pages, then bring the new back page up to date with
+                // a latch copy (a quarter of the memory accesses of a full
+                // copy). Animations that redraw every frame can call
+                // modex::flip alone.
+                modex::flip();
+                modex::copy_page(modex::visible_page(), modex::draw_page());
+            } else {
+                modex::copy_page(modex::draw_page(), modex::visible_page());
+            }
+            if cursor {
+                fb_cursor_show();
+            }
+        } else if DOUBLE_BUFFER_ENABLED {
+            // Copy from the top during blanking: the beam starts again at
+            // the top and the copy stays ahead of it
+            if vsync {
+                vga::wait_for_vblank();
+            }
+            for i in 0..fb_width() * fb_height() {
                 *FB_ADDR.add(i) = BACK_BUFFER[i];
             }
+            // The copy wiped the cursor; put it back over the new frame
+            if MOUSE_CURSOR_VISIBLE {
+                fb_cursor_draw();
+            }
         }
     }
 }
 
 // Enhanced clear function that works with double buffering
 fn fb_clear_enhanced(color: u8) {
+    match fb_backend() {
+        Backend::Planar => return planar::clear(color),
+        Backend::ModeX => return modex::clear(modex::draw_page(), color),
+        Backend::Bga => return bga::clear(color),
+        _ => {}
+    }
     unsafe {
         let buffer = get_draw_buffer();
-        for i in 0..FB_SIZE {
+        for i in 0..fb_width() * fb_height() {
             *buffer.add(i) = color;
         }
     }
@@ -495,16 +699,28 @@ fn fb_clear_enhanced(color: u8) {
 
 // Enhanced pixel setting that works with double buffering
 fn fb_set_pixel_enhanced(x: usize, y: usize, color: u8) {
-    if x < FB_WIDTH && y < FB_HEIGHT {
+    match fb_backend() {
+        Backend::Planar => return planar::set_pixel(x, y, color),
+        Backend::ModeX => return modex::set_pixel(modex::draw_page(), x, y, color),
+        Backend::Bga => return bga::set_pixel(x, y, color),
+        _ => {}
+    }
+    if x < fb_width() && y < fb_height() {
         unsafe {
             let buffer = get_draw_buffer();
-            *buffer.add(y * FB_WIDTH + x) = color;
+            *buffer.add(y * fb_width() + x) = color;
         }
     }
 }
 
 // Enhanced rectangle drawing
 fn fb_draw_rect_enhanced(x: usize, y: usize, w: usize, h: usize, color: u8) {
+    match fb_backend() {
+        Backend::Planar => return planar::fill_rect(x, y, w, h, color),
+        Backend::ModeX => return modex::fill_rect(modex::draw_page(), x, y, w, h, color),
+        Backend::Bga => return bga::fill_rect(x, y, w, h, color),
+        _ => {}
+    }
     for dy in 0..h {
         for dx in 0..w {
             fb_set_pixel_enhanced(x + dx, y + dy, color);
@@ -514,11 +730,17 @@ fn fb_draw_rect_enhanced(x: usize, y: usize, w: usize, h: usize, color: u8) {
 
 // Fast horizontal line for better performance
 fn fb_draw_hline(x: usize, y: usize, width: usize, color: u8) {
-    if y < FB_HEIGHT {
+    match fb_backend() {
+        Backend::Planar => return planar::fill_rect(x, y, width, 1, color),
+        Backend::ModeX => return modex::fill_rect(modex::draw_page(), x, y, width, 1, color),
+        Backend::Bga => return bga::fill_rect(x, y, width, 1, color),
+        _ => {}
+    }
+    if y < fb_height() && x < fb_width() {
         unsafe {
             let buffer = get_draw_buffer();
-            let start = y * FB_WIDTH + x;
-            let end = start + width.min(FB_WIDTH - x);
+            let start = y * fb_width() + x;
+            let end = start + width.min(fb_width() - x);
             for i in start..end {
                 *buffer.add(i) = color;
             }
@@ -528,11 +750,17 @@ fn fb_draw_hline(x: usize, y: usize, width: usize, color: u8) {
 
 // Fast vertical line for better performance
 fn fb_draw_vline(x: usize, y: usize, height: usize, color: u8) {
-    if x < FB_WIDTH {
+    match fb_backend() {
+        Backend::Planar => return planar::fill_rect(x, y, 1, height, color),
+        Backend::ModeX => return modex::fill_rect(modex::draw_page(), x, y, 1, height, color),
+        Backend::Bga => return bga::fill_rect(x, y, 1, height, color),
+        _ => {}
+    }
+    if x < fb_width() && y < fb_height() {
         unsafe {
             let buffer = get_draw_buffer();
-            for dy in 0..height.min(FB_HEIGHT - y) {
-                *buffer.add((y + dy) * FB_WIDTH + x) = color;
+            for dy in 0..height.min(fb_height() - y) {
+                *buffer.add((y + dy) * fb_width() + x) = color;
             }
         }
     }
@@ -541,9 +769,9 @@ fn fb_draw_vline(x: usize, y: usize, height: usize, color: u8) {
 // Enhanced sprite drawing with transparency support
 fn fb_draw_sprite_enhanced(x: usize, y: usize, sprite: &Sprite, sprite_data: &[&str], colors: &[u8]) {
     for (row, line) in sprite_data.iter().enumerate().take(sprite.height) {
-        if y + row >= FB_HEIGHT { break; }
+        if y + row >= fb_height() { break; }
         for (col, ch) in line.chars().enumerate().take(sprite.width) {
-            if x + col >= FB_WIDTH { break; }
+            if x + col >= fb_width() { break; }
             if let Some(color_index) = ch.to_digit(10) {
                 let color_idx = color_index as u8;
                 // Check for transparency
@@ -555,92 +783,91 @@ fn fb_draw_sprite_enhanced(x: usize, y: usize, sprite: &Sprite, sprite_data: &[&
     }
 }
 
+// Pick the frame to show at `elapsed_ms` into a looping animation
+fn animation_frame_index(frames: &[AnimationFrame], elapsed_ms: u64) -> usize {
+    let cycle_ms: u64 = frames.iter().map(|f| f.duration_ms as u64).sum();
+    if cycle_ms == 0 {
+        return 0;
+    }
+    let mut t = elapsed_ms % cycle_ms;
+    for (i, frame) in frames.iter().enumerate() {
+        if t < frame.duration_ms as u64 {
+            return i;
+        }
+        t -= frame.duration_ms as u64;
+    }
+    0
+}
+
 // Animation support - draw animated sprite
 fn fb_draw_animation(x: usize, y: usize, frames: &[AnimationFrame], sprite: &Sprite, colors: &[u8]) {
-    unsafe {
-        // Simple frame selection based on frame counter
-        let frame_idx = (FRAME_COUNTER / 10) % frames.len() as u32; // Change frame every 10 ticks
-        if let Some(frame) = frames.get(frame_idx as usize) {
-            fb_draw_sprite_enhanced(x, y, sprite, frame.sprite_data, colors);
-        }
+    let frame_idx = if timer::frequency() != 0 {
+        // Real time: each frame stays up for its own duration_ms
+        animation_frame_index(frames, timer::uptime_ms())
+    } else {
+        // No timer running: fall back to changing frame every 10 counter ticks
+        unsafe { ((FRAME_COUNTER / 10) % frames.len().max(1) as u32) as usize }
+    };
+    if let Some(frame) = frames.get(frame_idx) {
+        fb_draw_sprite_enhanced(x, y, sprite, frame.sprite_data, colors);
     }
 }
 
 // Blit one area of the screen to another (useful for scrolling)
 fn fb_blit(src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, w: usize, h: usize) {
+    // Clip both areas here so the backends only see on-screen copies
+    let (w, h) = clip_area(src_x.max(dst_x), src_y.max(dst_y), w, h);
+    if w == 0 || h == 0 {
+        return;
+    }
+    match fb_backend() {
+        Backend::Planar => return planar::copy_rect(src_x, src_y, dst_x, dst_y, w, h),
+        Backend::ModeX => return modex::copy_rect(modex::draw_page(), src_x, src_y, dst_x, dst_y, w, h),
+        Backend::Bga => return bga::copy_rect(src_x, src_y, dst_x, dst_y, w, h),
+        _ => {}
+    }
+    let width = fb_width();
+    let (rows_up, cols_back) = copy_direction(src_x, src_y, dst_x, dst_y);
     unsafe {
         let buffer = get_draw_buffer();
-        for dy in 0..h {
-            if src_y + dy >= FB_HEIGHT || dst_y + dy >= FB_HEIGHT { continue; }
-            for dx in 0..w {
-                if src_x + dx >= FB_WIDTH || dst_x + dx >= FB_WIDTH { continue; }
-                let src_pixel = *buffer.add((src_y + dy) * FB_WIDTH + (src_x + dx));
-                *buffer.add((dst_y + dy) * FB_WIDTH + (dst_x + dx)) = src_pixel;
+        for i in 0..h {
+            let dy = if rows_up { h - 1 - i } else { i };
+            for j in 0..w {
+                let dx = if cols_back { w - 1 - j } else { j };
+                let src_pixel = *buffer.add((src_y + dy) * width + (src_x + dx));
+                *buffer.add((dst_y + dy) * width + (dst_x + dx)) = src_pixel;
             }
         }
     }
 }
 
-// Screen scrolling functions
+// Screen scrolling functions. Every backend scrolls by copying with fb_blit
+// and filling the uncovered lines.
 fn fb_scroll_up(lines: usize, fill_color: u8) {
-    if lines >= FB_HEIGHT {
-        fb_clear_enhanced(fill_color);
-        return;
-    }
-    
-    unsafe {
-        let buffer = get_draw_buffer();
-        // Move pixels up
-        for y in lines..FB_HEIGHT {
-            for x in 0..FB_WIDTH {
-                let src = y * FB_WIDTH + x;
-                let dst = (y - lines) * FB_WIDTH + x;
-                *buffer.add(dst) = *buffer.add(src);
-            }
-        }
-        // Fill bottom with fill_color
-        for y in (FB_HEIGHT - lines)..FB_HEIGHT {
-            for x in 0..FB_WIDTH {
-                *buffer.add(y * FB_WIDTH + x) = fill_color;
-            }
-        }
-    }
+    let (width, height) = (fb_width(), fb_height());
+    let lines = lines.min(height);
+    fb_blit(0, lines, 0, 0, width, height - lines);
+    fb_draw_rect_enhanced(0, height - lines, width, lines, fill_color);
 }
 
 fn fb_scroll_down(lines: usize, fill_color: u8) {
-    if lines >= FB_HEIGHT {
-        fb_clear_enhanced(fill_color);
-        return;
-    }
-    
-    unsafe {
-        let buffer = get_draw_buffer();
-        // Move pixels down (start from bottom)
-        for y in (0..(FB_HEIGHT - lines)).rev() {
-            for x in 0..FB_WIDTH {
-                let src = y * FB_WIDTH + x;
-                let dst = (y + lines) * FB_WIDTH + x;
-                *buffer.add(dst) = *buffer.add(src);
-            }
-        }
-        // Fill top with fill_color
-        for y in 0..lines {
-            for x in 0..FB_WIDTH {
-                *buffer.add(y * FB_WIDTH + x) = fill_color;
-            }
-        }
-    }
+    let (width, height) = (fb_width(), fb_height());
+    let lines = lines.min(height);
+    fb_blit(0, 0, 0, lines, width, height - lines);
+    fb_draw_rect_enhanced(0, 0, width, lines, fill_color);
 }
 
 // Get pixel color at position (useful for collision detection)
 fn fb_get_pixel(x: usize, y: usize) -> u8 {
-    if x < FB_WIDTH && y < FB_HEIGHT {
-        unsafe {
+    match fb_backend() {
+        Backend::Planar => planar::get_pixel(x, y),
+        Backend::ModeX => modex::get_pixel(modex::draw_page(), x, y),
+        Backend::Bga => bga::get_pixel(x, y),
+        _ if x < fb_width() && y < fb_height() => unsafe {
             let buffer = get_draw_buffer();
-            *buffer.add(y * FB_WIDTH + x)
-        }
-    } else {
-        0
+            *buffer.add(y * fb_width() + x)
+        },
+        _ => 0,
     }
 }
 
@@ -654,15 +881,15 @@ fn fb_draw_text_enhanced(x: usize, y: usize, text: &str, color: u8) {
             b'\n' => {
                 char_y += 8; // Move to next line
                 char_x = x;  // Reset to start of line
-                if char_y + 8 >= FB_HEIGHT { break; }
+                if char_y + 8 >= fb_height() { break; }
             }
             b'\r' => char_x = x, // Carriage return
             _ => {
-                if char_x + 8 >= FB_WIDTH {
+                if char_x + 8 >= fb_width() {
                     // Auto-wrap to next line
                     char_y += 8;
                     char_x = x;
-                    if char_y + 8 >= FB_HEIGHT { break; }
+                    if char_y + 8 >= fb_height() { break; }
                 }
                 let font_data = get_font_char(c);
                 fb_blit_bitmap_enhanced(char_x, char_y, 8, 8, &font_data, color);
@@ -674,13 +901,16 @@ fn fb_draw_text_enhanced(x: usize, y: usize, text: &str, color: u8) {
 
 // Enhanced bitmap blitting with double buffer support
 fn fb_blit_bitmap_enhanced(x: usize, y: usize, w: usize, h: usize, bitmap: &[u8], color: u8) {
-    for row in 0..h {
-        if y + row >= FB_HEIGHT { break; }
-        for col in 0..w {
-            if x + col >= FB_WIDTH { break; }
-            let byte_idx = (row * ((w + 7) / 8)) + (col / 8);
-            let bit = 7 - (col % 8);
-            if byte_idx < bitmap.len() && (bitmap[byte_idx] & (1 << bit)) != 0 {
+    match fb_backend() {
+        Backend::Planar => return planar::draw_bitmap(x, y, w, h, bitmap, color),
+        Backend::ModeX => return modex::draw_bitmap(modex::draw_page(), x, y, w, h, bitmap, color),
+        Backend::Bga => return bga::draw_bitmap(x, y, w, h, bitmap, color),
+        _ => {}
+    }
+    let (visible_w, visible_h) = clip_area(x, y, w, h);
+    for row in 0..visible_h {
+        for col in 0..visible_w {
+            if bitmap_bit(bitmap, w, col, row) {
                 fb_set_pixel_enhanced(x + col, y + row, color);
             }
         }
@@ -715,7 +945,7 @@ fn fb_draw_filled_circle(cx: usize, cy: usize, radius: usize, color: u8) {
             let dx = x - cx;
             let dy = y - cy;
             if dx * dx + dy * dy <= r_sq {
-                if x >= 0 && x < FB_WIDTH as isize && y >= 0 && y < FB_HEIGHT as isize {
+                if x >= 0 && x < fb_width() as isize && y >= 0 && y < fb_height() as isize {
                     fb_set_pixel(x as usize, y as usize, color);
                 }
             }
@@ -737,7 +967,9 @@ fn fb_draw_rect_outline(x: usize, y: usize, w: usize, h: usize, color: u8, thick
     }
 }
 
-// Draw a gradient rectangle (vertical gradient)
+// Draw a gradient rectangle (vertical gradient) through the palette indices
+// from start_color to end_color; load a ramp there (palette::ramp) for a
+// smooth color gradient
 fn fb_draw_gradient_rect(x: usize, y: usize, w: usize, h: usize, start_color: u8, end_color: u8) {
     for row in 0..h {
         let ratio = (row * 255) / h.max(1);
@@ -784,8 +1016,12 @@ fn fb_draw_window(x: usize, y: usize, w: usize, h: usize, title: &str, bg_color:
     fb_draw_text(x + 4, y + 4, title, 0x00);
 }
 
-// Create a simple color palette for VGA Mode 13h
+// Create a simple color palette for VGA Mode 13h. In 16-color Mode 12h the
+// attribute controller already maps pixel values 0-15 to these colors.
 fn get_palette_color(index: u8) -> u8 {
+    if fb_backend() == Backend::Planar {
+        return index % 16;
+    }
     match index % 16 {
         0 => 0x00,  // Black
         1 => 0x01,  // Dark Blue
@@ -820,6 +1056,131 @@ fn fb_draw_sprite(x: usize, y: usize, sprite_data: &[&str], colors: &[u8]) {
     }
 }
 
+// --- Mouse cursor ---
+// Drawn straight onto the screen with save-under: the pixels it covers are
+// kept and put back before it moves, so the picture below is never damaged.
+// Code drawing directly to the screen under the cursor must hide it first.
+const MOUSE_CURSOR_WIDTH: usize = 8;
+const MOUSE_CURSOR_HEIGHT: usize = 12;
+const MOUSE_CURSOR_SPRITE: [&str; MOUSE_CURSOR_HEIGHT] = [
+    "1.......",
+    "11......",
+    "121.....",
+    "1221....",
+    "12221...",
+    "122221..",
+    "1222221.",
+    "12222221",
+    "1222111.",
+    "121221..",
+    "11.1221.",
+    "....11..",
+];
+static mut MOUSE_CURSOR_X: usize = 0;
+static mut MOUSE_CURSOR_Y: usize = 0;
+static mut MOUSE_CURSOR_VISIBLE: bool = false;
+static mut MOUSE_CURSOR_SAVE: [u8; MOUSE_CURSOR_WIDTH * MOUSE_CURSOR_HEIGHT] = [0; MOUSE_CURSOR_WIDTH * MOUSE_CURSOR_HEIGHT];
+
+// The cursor lives on the visible screen even while drawing goes to the
+// back buffer
+fn screen_pixel(x: usize, y: usize) -> u8 {
+    match fb_backend() {
+        Backend::Planar => planar::get_pixel(x, y),
+        Backend::ModeX => modex::get_pixel(modex::visible_page(), x, y),
+        Backend::Bga => bga::get_pixel(x, y),
+        _ => unsafe { *FB_ADDR.add(y * fb_width() + x) },
+    }
+}
+
+fn set_screen_pixel(x: usize, y: usize, color: u8) {
+    match fb_backend() {
+        Backend::Planar => planar::set_pixel(x, y, color),
+        Backend::ModeX => modex::set_pixel(modex::visible_page(), x, y, color),
+        Backend::Bga => bga::set_pixel(x, y, color),
+        _ => unsafe { *FB_ADDR.add(y * fb_width() + x) = color },
+    }
+}
+
+// Save what lies under the cursor, then draw it
+fn fb_cursor_draw() {
+    unsafe {
+        let (x, y) = (MOUSE_CURSOR_X, MOUSE_CURSOR_Y);
+        for (row, line) in MOUSE_CURSOR_SPRITE.iter().enumerate() {
+            for (col, ch) in line.bytes().enumerate() {
+                let (px, py) = (x + col, y + row);
+                if px >= fb_width() || py >= fb_height() {
+                    continue;
+                }
+                MOUSE_CURSOR_SAVE[row * MOUSE_CURSOR_WIDTH + col] = screen_pixel(px, py);
+                match ch {
+                    b'1' => set_screen_pixel(px, py, get_palette_color(0)),
+                    b'2' => set_screen_pixel(px, py, get_palette_color(15)),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+// Put back the pixels the cursor covered
+fn fb_cursor_erase() {
+    unsafe {
+        let (x, y) = (MOUSE_CURSOR_X, MOUSE_CURSOR_Y);
+        for row in 0..MOUSE_CURSOR_HEIGHT {
+            for col in 0..MOUSE_CURSOR_WIDTH {
+                let (px, py) = (x + col, y + row);
+                if px < fb_width() && py < fb_height() {
+                    set_screen_pixel(px, py, MOUSE_CURSOR_SAVE[row * MOUSE_CURSOR_WIDTH + col]);
+                }
+            }
+        }
+    }
+}
+
+fn fb_cursor_show() {
+    unsafe {
+        if !MOUSE_CURSOR_VISIBLE {
+            fb_cursor_draw();
+            MOUSE_CURSOR_VISIBLE = true;
+        }
+    }
+}
+
+fn fb_cursor_hide() {
+    unsafe {
+        if MOUSE_CURSOR_VISIBLE {
+            fb_cursor_erase();
+            MOUSE_CURSOR_VISIBLE = false;
+        }
+    }
+}
+
+// Move the cursor by a relative amount, clamped to the screen
+fn fb_cursor_move(dx: i16, dy: i16) {
+    // No screen to move over; the clamps below need a nonzero size
+    if fb_backend() == Backend::Text {
+        return;
+    }
+    unsafe {
+        let x = (MOUSE_CURSOR_X as isize + dx as isize).clamp(0, fb_width() as isize - 1) as usize;
+        let y = (MOUSE_CURSOR_Y as isize + dy as isize).clamp(0, fb_height() as isize - 1) as usize;
+        if (x, y) == (MOUSE_CURSOR_X, MOUSE_CURSOR_Y) {
+            return;
+        }
+        let visible = MOUSE_CURSOR_VISIBLE;
+        fb_cursor_hide();
+        MOUSE_CURSOR_X = x;
+        MOUSE_CURSOR_Y = y;
+        if visible {
+            fb_cursor_show();
+        }
+    }
+}
+
+fn fb_cursor_position() -> (usize, usize) {
+    unsafe { (MOUSE_CURSOR_X, MOUSE_CURSOR_Y) }
+}
+
 // --- Minimal PS/2 keyboard input ---
 fn keyboard_poll() -> Option<u8> {
     let mut scancode = None;
@@ -836,6 +1197,7 @@ fn keyboard_poll() -> Option<u8> {
 }
 
 // --- Simple bump allocator for heap memory ---
+const HEAP_FRAMES: usize = 256; // 1 MiB
 static mut BUMP_PTR: usize = 0;
 static mut BUMP_END: usize = 0;
 
@@ -869,46 +1231,278 @@ fn halt() -> ! {
 pub unsafe extern "C" fn long_mode_start() -> ! {
     core::arch::naked_asm!(
         "mov rsp, 0x90000",
+        // Enable SSE (clear CR0.EM, set CR0.MP, set CR4.OSFXSR/OSXMMEXCPT):
+        // the compiler emits SSE and the interrupt entry uses fxsave
+        "mov rax, cr0",
+        "and ax, 0xFFFB",
+        "or ax, 0x2",
+        "mov cr0, rax",
+        "mov rax, cr4",
+        "or ax, 0x600",
+        "mov cr4, rax",
+        // boot.asm left the Multiboot2 magic in esi and the info address in
+        // ebx; the 32-bit moves zero the undefined upper halves.
+        "mov edi, esi",
+        "mov esi, ebx",
         "call _start",
         "hlt"
     );
 }
 
+// Bring up the PS/2 controller and report what was found, so dead input
+// comes with an explanation on screen
+fn init_input() {
+    if let Err(err) = ps2::init() {
+        error!("PS/2 controller: {}, polling the keyboard instead", err.description());
+        return;
+    }
+    for (port, number) in [(ps2::Port::First, 1), (ps2::Port::Second, 2)] {
+        match ps2::port_status(port) {
+            Ok(device) => info!("PS/2 port {}: {}", number, device.name()),
+            Err(ps2::Ps2Error::NoSecondPort) => {}
+            Err(err) => error!("PS/2 port {}: {}", number, err.description()),
+        }
+    }
+    if !keyboard::init() {
+        error!("No PS/2 keyboard found");
+    }
+    if mouse::init() {
+        info!("Mouse enabled{}", if mouse::has_wheel() { " with wheel" } else { "" });
+    }
+}
+
+// Sleep until the keyboard or mouse has something for us. The check runs
+// with interrupts off so input arriving just before `hlt` still wakes us.
+fn wait_for_input() {
+    irq::disable_interrupts();
+    if keyboard::has_key() || mouse::has_data() {
+        irq::enable_interrupts();
+    } else {
+        unsafe { asm!("sti", "hlt", options(nomem, nostack)); }
+    }
+}
+
+fn escape_pressed() -> bool {
+    while let Some(event) = keyboard::try_read_event() {
+        if event.pressed && event.code == keymap::KeyCode::Escape {
+            return true;
+        }
+    }
+    false
+}
+
+// DAC entries for the demo's header gradient and color-cycled stripe
+const GRADIENT_START: u8 = 0xC0;
+const CYCLE_START: u8 = 0xE8;
+const RAINBOW: [palette::Rgb; 7] = [
+    palette::Rgb::new(255, 0, 0),
+    palette::Rgb::new(255, 128, 0),
+    palette::Rgb::new(255, 255, 0),
+    palette::Rgb::new(0, 255, 0),
+    palette::Rgb::new(0, 128, 255),
+    palette::Rgb::new(64, 0, 255),
+    palette::Rgb::new(192, 0, 255),
+];
+
+// Load a palette file passed as a GRUB module called "palette", e.g.
+// `module2 /boot/colors.pal palette`, over the default colors
+fn load_palette_module(info: &multiboot::BootInfo) {
+    let Some(module) = info.modules().find(|module| module.cmdline.trim() == "palette") else {
+        return;
+    };
+    let len = module.end.saturating_sub(module.start) as usize;
+    let bytes = unsafe { core::slice::from_raw_parts(module.start as usize as *const u8, len) };
+    match palette::load_file(bytes, 0) {
+        Ok(count) => info!("Loaded {} colors from the palette module", count),
+        Err(err) => warn!("Palette module: {}", err.description()),
+    }
+}
+
+// A ball bouncing over a fixed background in Mode X (the caller switches
+// modes). The background is
+// drawn once on the spare page and copied under every frame through the
+// latches; flipping pages then shows the frame without tearing.
+fn modex_demo() {
+    let (width, height) = (fb_width(), fb_height());
+    const BACKGROUND: usize = 2;
+    modex::set_draw_page(BACKGROUND);
+    fb_clear_enhanced(get_palette_color(1));
+    for row in 0..height / 8 {
+        fb_draw_rect_enhanced(0, row * 8, width, 4, get_palette_color(if row % 2 == 0 { 1 } else { 9 }));
+    }
+    fb_draw_text_enhanced(8, 8, "Mode X - 320x240, page flipping", get_palette_color(15));
+    fb_draw_text_enhanced(8, height - 16, "Press Escape to continue", get_palette_color(14));
+    modex::set_draw_page(1);
+
+    let ball = Sprite { width: 7, height: 7, transparent_color: 0 };
+    let ball_data = ["0011100", "0122210", "1222221", "1223221", "1222221", "0122210", "0011100"];
+    let ball_colors = [0x00, get_palette_color(0), get_palette_color(14), get_palette_color(12)];
+    let (mut x, mut y, mut dx, mut dy) = (20isize, 40isize, 2isize, 1isize);
+    while !escape_pressed() {
+        modex::copy_page(BACKGROUND, modex::draw_page());
+        fb_draw_sprite_enhanced(x as usize, y as usize, &ball, &ball_data, &ball_colors);
+        modex::flip();
+        if x + dx < 0 || x + dx + ball.width as isize > width as isize { dx = -dx; }
+        if y + dy < 24 || y + dy + ball.height as isize > height as isize - 24 { dy = -dy; }
+        x += dx;
+        y += dy;
+    }
+}
+
+// A 640x480 dashboard in Mode 12h (the caller switches modes), shown until
+// Escape is pressed
+fn planar_demo() {
+    let (width, height) = (fb_width(), fb_height());
+    fb_clear(get_palette_color(1));
+    fb_draw_rect(0, 0, width, 24, get_palette_color(9));
+    fb_draw_text(8, 8, "Mode 12h - 640x480, 16 colors", get_palette_color(15));
+    for i in 0..16 {
+        fb_draw_rect(8 + i * 39, 36, 36, 20, get_palette_color(i as u8));
+    }
+    fb_draw_window(40, 80, 560, 320, "Dashboard", get_palette_color(7), get_palette_color(3), get_palette_color(0));
+    // A bar chart with a baseline
+    let values = [120, 200, 90, 260, 180, 230, 150, 280];
+    for (i, &value) in values.iter().enumerate() {
+        let x = 80 + i * 60;
+        fb_draw_rect(x, 380 - value, 40, value, get_palette_color(10 + (i % 5) as u8));
+        fb_draw_rect_outline(x, 380 - value, 40, value, get_palette_color(0), 1);
+    }
+    fb_draw_line(70, 380, 570, 380, get_palette_color(0));
+    fb_draw_text(8, height - 16, "Press Escape to continue", get_palette_color(15));
+    follow_mouse_until_escape();
+}
+
+// Every palette color and a window at 1024x768 in 32 bpp on the Bochs
+// adapter (the caller switches modes), shown until Escape is pressed
+fn bga_demo() {
+    let (width, height) = (fb_width(), fb_height());
+    fb_clear(get_palette_color(1));
+    fb_draw_rect(0, 0, width, 24, get_palette_color(9));
+    fb_draw_text(8, 8, "Bochs graphics adapter - 1024x768, 32 bpp", get_palette_color(15));
+    // The 256 colors, 16 to a row
+    for i in 0..256 {
+        fb_draw_rect(32 + (i % 16) * 28, 48 + (i / 16) * 28, 24, 24, i as u8);
+    }
+    fb_draw_window(528, 48, 464, 160, "Display", get_palette_color(7), get_palette_color(3), get_palette_color(0));
+    fb_draw_text(544, 88, "Linear framebuffer from PCI BAR 0", get_palette_color(0));
+    fb_draw_text(544, 104, "Palette indices drawn as RGB", get_palette_color(0));
+    fb_draw_text(8, height - 16, "Press Escape to return to text mode", get_palette_color(15));
+    follow_mouse_until_escape();
+}
+
+fn follow_mouse_until_escape() {
+    fb_cursor_show();
+    while !escape_pressed() {
+        while let Some(event) = mouse::try_read_event() {
+            fb_cursor_move(event.dx, event.dy);
+        }
+        wait_for_input();
+    }
+    fb_cursor_hide();
+}
+
 // --- Kernel main entry point ---
 #[no_mangle]
-pub extern "C" fn _start() -> ! {
+pub extern "C" fn _start(multiboot_magic: u32, multiboot_info: usize) -> ! {
+    gdt::init();
     init_idt();
+    irq::init();
+    let serial_ok = serial::init(serial::DEFAULT_BAUD);
+    timer::init(timer::DEFAULT_FREQUENCY);
+    irq::enable_interrupts();
+    vt::init();
     vga_clear();
-    vga_print("Welcome to your Rust OS kernel!\n", 0x2f);
-    vga_print("Text mode is working.\n", 0x2f);
-    vga_print("Testing heap allocation...\n", 0x2f);
+    vt::write(vt::SHELL_CONSOLE, b"Shell console - Alt+F1 returns to the kernel log\n", 0x2f);
+    println!("Welcome to your Rust OS kernel!");
+    println!("Text mode is working.");
+    if serial_ok {
+        info!("Console mirrored to COM1 at {} baud", serial::DEFAULT_BAUD);
+    }
+    let boot_info = unsafe { multiboot::init(multiboot_magic, multiboot_info) };
+    match boot_info {
+        Some(info) => {
+            if let Some(cmdline) = info.command_line() {
+                log::configure(cmdline);
+            }
+            if let Some(name) = info.boot_loader_name() {
+                info!("Boot loader: {}", name);
+            }
+            if let Some(cmdline) = info.command_line() {
+                info!("Command line: {}", cmdline);
+            }
+            info!("Usable memory: {} KiB", info.usable_memory() / 1024);
+        }
+        None => error!("No valid Multiboot2 information!"),
+    }
+    init_input();
+    memory::init(boot_info.as_ref());
+    info!("Physical memory: {} KiB, {} KiB free", memory::total_memory() / 1024, memory::free_memory() / 1024);
+    if paging::init().is_err() {
+        error!("Failed to build kernel page tables");
+    } else {
+        // Unmap the lowest boot stack page so an overflow faults (and lands
+        // on the double-fault IST stack) instead of running into low memory
+        let _ = paging::unmap_page(memory::BOOT_STACK.0);
+    }
+    if acpi::init(boot_info.and_then(|info| info.rsdp())) {
+        if let Some(madt) = acpi::parse_madt() {
+            if irq::use_apic(&madt) {
+                info!("Interrupts routed through the I/O APIC");
+            }
+        }
+    }
+    for device in pci::devices() {
+        info!("PCI {:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}.{:02x}", device.bus, device.slot, device.function,
+            device.vendor_id, device.device_id, device.class, device.subclass);
+    }
+    if let Some(version) = bga::version() {
+        info!("Bochs graphics adapter, interface version {}", version);
+    }
+    println!("Testing heap allocation...");
     unsafe {
-        bump_init(0x100000, 0x200000);
+        // Carve the heap out of free physical memory instead of a fixed
+        // range that would overlap the kernel image at 1 MiB
+        if let Some(frame) = memory::allocate_contiguous(HEAP_FRAMES) {
+            let start = frame.start_address();
+            bump_init(start, start + HEAP_FRAMES * memory::FRAME_SIZE);
+        }
         let ptr1 = bump_alloc(64);
         let ptr2 = bump_alloc(128);
         if !ptr1.is_null() && !ptr2.is_null() {
-            vga_print("Heap allocation OK\n", 0x2f);
+            debug!("Heap allocation OK at {:#x}", ptr1 as usize);
         } else {
-            vga_print("Heap allocation FAILED\n", 0x4f);
+            error!("Heap allocation FAILED");
         }
     }
-    vga_print("Switching to graphics mode...\n", 0x2f);
+    println!("Switching to graphics mode...");
     init_graphics_mode();
     
     // --- Simple Graphics Demo ---
     // Test basic framebuffer access
     unsafe {
         // Fill screen with a simple pattern to test if graphics mode works
-        for i in 0..FB_SIZE {
-            *FB_ADDR.add(i) = ((i / FB_WIDTH) % 256) as u8;
+        for i in 0..fb_width() * fb_height() {
+            *FB_ADDR.add(i) = ((i / fb_width()) % 256) as u8;
         }
     }
     
+    // Give the header a real blue ramp and the stripe a rainbow to cycle,
+    // in DAC entries the 16 standard colors don't use, then draw everything
+    // while the palette is black and fade it in
+    if let Some(info) = boot_info {
+        load_palette_module(&info);
+    }
+    let boot_palette = palette::save();
+    palette::ramp(GRADIENT_START, 40, palette::Rgb::new(0, 0, 80), palette::Rgb::new(80, 160, 255));
+    palette::set_range(CYCLE_START, &RAINBOW);
+    let demo_palette = palette::save();
+    palette::load(&[palette::Rgb::BLACK; palette::COLORS]);
+
     // Clear screen to blue
     fb_clear(get_palette_color(1));
     
     // Draw gradient background
-    fb_draw_gradient_rect(0, 0, FB_WIDTH, 40, get_palette_color(1), get_palette_color(9));
+    fb_draw_gradient_rect(0, 0, fb_width(), 40, GRADIENT_START, GRADIENT_START + 39);
     
     // Draw title text
     fb_draw_text(10, 10, "Rust OS - Graphics Demo", get_palette_color(15));
@@ -949,20 +1543,70 @@ pub extern "C" fn _start() -> ! {
     }
     fb_draw_text(10, 42, "Color Palette:", get_palette_color(15));
     
-    // Draw some lines for decoration
-    for i in 0..5 {
-        fb_draw_line(20, 170 + i * 2, 300, 170 + i * 2, get_palette_color(8 + i as u8));
+    // A rainbow stripe; rotating its palette entries makes it flow
+    for i in 0..35 {
+        fb_draw_rect(20 + i * 8, 168, 8, 10, CYCLE_START + (i % RAINBOW.len()) as u8);
     }
-    
-    loop {
-        if let Some(sc) = keyboard_poll() {
-            if sc == 0x01 {
-                break;
+    palette::fade_from_black(&demo_palette, 500);
+    // The loop runs once per refresh; the stripe moves every fourth frame
+    const CYCLE_FRAMES: u64 = 4;
+    let mut pacer = vga::FramePacer::new(1);
+
+    // Follow the mouse and report button clicks until Escape is pressed
+    let buttons = [(80, 130, 60, 20, "OK"), (150, 130, 60, 20, "Cancel")];
+    let mut held = 0;
+    fb_cursor_show();
+    'demo: loop {
+        while let Some(event) = keyboard::try_read_event() {
+            if event.pressed && event.code == keymap::KeyCode::Escape {
+                break 'demo;
+            }
+        }
+        // Right at the start of the retrace, so no frame shows two steps
+        pacer.wait();
+        if pacer.frames().is_multiple_of(CYCLE_FRAMES) {
+            palette::rotate_range(CYCLE_START, CYCLE_START + RAINBOW.len() as u8 - 1, 1);
+        }
+        while let Some(event) = mouse::try_read_event() {
+            fb_cursor_move(event.dx, event.dy);
+            let pressed = event.buttons & !held;
+            held = event.buttons;
+            if pressed & mouse::BUTTON_LEFT == 0 {
+                continue;
+            }
+            let (mx, my) = fb_cursor_position();
+            for &(x, y, w, h, label) in buttons.iter() {
+                if mx >= x && mx < x + w && my >= y && my < y + h {
+                    fb_cursor_hide();
+                    fb_draw_rect(10, 190, fb_width() - 10, 8, get_palette_color(1));
+                    fb_draw_text(10, 190, "Clicked:", get_palette_color(15));
+                    fb_draw_text(82, 190, label, get_palette_color(15));
+                    fb_cursor_show();
+                }
             }
         }
     }
-    
-    halt();
+
+    palette::fade_to_black(400);
+    set_video_mode(VIDEO_MODES[3]);
+    palette::load(&boot_palette);
+    modex_demo();
+    set_video_mode(VIDEO_MODES[1]);
+    planar_demo();
+    // Only with an emulator's adapter, e.g. QEMU's -vga std
+    if set_video_mode(VIDEO_MODES[4]) {
+        bga_demo();
+    }
+    set_video_mode(VIDEO_MODES[2]);
+    println!("Back in text mode");
+    // Messages logged while in graphics mode only reached the small fbcon
+    // screen; show the whole kept log again
+    log::dmesg();
+    // Keep reading the keyboard so Alt+Fn and Shift+PgUp/PgDn still work
+    loop {
+        while keyboard::try_read_event().is_some() {}
+        wait_for_input();
+    }
 }
 
 // --- Miniqemu-system-x86_64 -cdrom build/os-x86_64.iso -vga stdmal 64-bit IDT entry (interrupt gate, present, DPL=0) ---
@@ -1010,6 +1654,11 @@ unsafe fn set_idt_entry(idx: usize, handler: extern "C" fn()) {
     };
 }
 
+// Select an Interrupt Stack Table slot (1-7, 0 = none) for a vector
+unsafe fn set_idt_ist(idx: usize, ist: u8) {
+    IDT.0[idx].ist = ist & 0x7;
+}
+
 #[repr(C, packed)]
 struct IdtPtr {
     limit: u16,
@@ -1022,6 +1671,12 @@ pub extern "C" fn init_idt() {
         for i in 0..256 {
             set_idt_entry(i, default_handler);
         }
+        interrupts::install_exception_handlers();
+        interrupts::install_irq_handlers();
+        // Faults that can be caused by a broken kernel stack run on their own
+        set_idt_ist(2, gdt::NMI_IST);
+        set_idt_ist(8, gdt::DOUBLE_FAULT_IST);
+        set_idt_ist(18, gdt::MACHINE_CHECK_IST);
         let idt_ptr = IdtPtr {
             limit: core::mem::size_of::<Idt>() as u16 - 1,
             base: &IDT as *const _ as u64,
@@ -1034,12 +1689,12 @@ pub extern "C" fn init_idt() {
 
 // --- Custom panic handler (must be last) ---
 #[panic_handler]
-fn panic(_: &PanicInfo) -> ! {
-    vga_clear();
-    unsafe {
-        CURSOR_ROW = 0;
-        CURSOR_COL = 0;
+fn panic(info: &PanicInfo) -> ! {
+    unsafe { asm!("cli", options(nomem, nostack)); }
+    // Keep whatever is on screen (e.g. an exception register dump)
+    println_color!(console::COLOR_ERROR, "\nKERNEL PANIC! {}", info.message());
+    if let Some(location) = info.location() {
+        println_color!(console::COLOR_ERROR, "  at {}:{}:{}", location.file(), location.line(), location.column());
     }
-    vga_print("KERNEL PANIC!\n", 0x4f);
     halt();
 }
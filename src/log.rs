@@ -0,0 +1,243 @@
+// --- Leveled kernel log ---
+// error!/warn!/info!/debug!/trace! format a message once, stamp it with the
+// system tick and keep it in a fixed ring of records, so the history survives
+// scrolling and mode switches and can be replayed later (see dmesg()).
+// Records at or above the console level are also printed right away, which
+// mirrors them to serial. Verbosity is set globally and per module.
+
+use core::fmt::{self, Write};
+
+use crate::console;
+use crate::irq;
+use crate::timer;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace]
+            .into_iter()
+            .find(|level| level.name().eq_ignore_ascii_case(name))
+    }
+
+    fn color(&self) -> u8 {
+        match self {
+            Level::Error => console::COLOR_ERROR,
+            Level::Warn => 0x6f,
+            _ => console::COLOR_DEFAULT,
+        }
+    }
+}
+
+const LOG_CAPACITY: usize = 128;
+// Longer messages are truncated
+const MESSAGE_LEN: usize = 120;
+const MAX_FILTERS: usize = 8;
+
+#[derive(Copy, Clone)]
+pub struct Record {
+    pub level: Level,
+    pub timestamp_ns: u64,
+    pub module: &'static str,
+    len: usize,
+    text: [u8; MESSAGE_LEN],
+}
+
+impl Record {
+    const EMPTY: Record = Record { level: Level::Trace, timestamp_ns: 0, module: "", len: 0, text: [0; MESSAGE_LEN] };
+
+    pub fn message(&self) -> &str {
+        // Truncation may have split a character; drop the partial tail
+        match core::str::from_utf8(&self.text[..self.len]) {
+            Ok(s) => s,
+            Err(e) => core::str::from_utf8(&self.text[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+// Formats as "[   1.234567] INFO  ps2: message"
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.timestamp_ns / 1_000_000_000;
+        let micros = self.timestamp_ns / 1000 % 1_000_000;
+        write!(f, "[{:5}.{:06}] {:5} {}: {}", secs, micros, self.level.name(), self.module, self.message())
+    }
+}
+
+// Fills a record's text buffer, silently cutting off what does not fit
+struct TextBuffer<'a> {
+    buf: &'a mut [u8; MESSAGE_LEN],
+    len: usize,
+}
+
+impl Write for TextBuffer<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(MESSAGE_LEN - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+static mut RECORDS: [Record; LOG_CAPACITY] = [Record::EMPTY; LOG_CAPACITY];
+// Total records ever written; the oldest kept one is at WRITTEN - LOG_CAPACITY
+static mut WRITTEN: usize = 0;
+static mut MAX_LEVEL: Level = Level::Info;
+static mut CONSOLE_LEVEL: Level = Level::Info;
+static mut FILTERS: [Option<(&'static str, Level)>; MAX_FILTERS] = [None; MAX_FILTERS];
+
+// Module paths without the crate name, e.g. "keyboard" or "paging"
+fn short_module(path: &'static str) -> &'static str {
+    match path.find("::") {
+        Some(i) => &path[i + 2..],
+        None => path,
+    }
+}
+
+// Records more verbose than `level` are discarded
+pub fn set_max_level(level: Level) {
+    unsafe { MAX_LEVEL = level; }
+}
+
+// Records more verbose than `level` are kept but not printed
+pub fn set_console_level(level: Level) {
+    unsafe { CONSOLE_LEVEL = level; }
+}
+
+// Override the level for a module and its submodules ("ps2", "apic", ...).
+// Returns false if the filter table is full.
+pub fn set_module_level(module: &'static str, level: Level) -> bool {
+    irq::without_interrupts(|| unsafe {
+        let filters = &mut *core::ptr::addr_of_mut!(FILTERS);
+        if let Some(slot) = filters.iter_mut().flatten().find(|(m, _)| *m == module) {
+            slot.1 = level;
+            return true;
+        }
+        match filters.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some((module, level));
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+// Most specific filter wins; modules without one use the global level
+fn level_for(module: &str) -> Level {
+    let mut best: Option<(usize, Level)> = None;
+    unsafe {
+        for &(prefix, level) in (*core::ptr::addr_of!(FILTERS)).iter().flatten() {
+            let matches = module == prefix
+                || (module.starts_with(prefix) && module[prefix.len()..].starts_with("::"));
+            if matches && best.is_none_or(|(len, _)| prefix.len() > len) {
+                best = Some((prefix.len(), level));
+            }
+        }
+        best.map_or(MAX_LEVEL, |(_, level)| level)
+    }
+}
+
+pub fn enabled(level: Level, module_path: &'static str) -> bool {
+    level <= level_for(short_module(module_path))
+}
+
+// Apply "loglevel=<level>" and "log.<module>=<level>" options from the
+// kernel command line
+pub fn configure(cmdline: &'static str) {
+    for option in cmdline.split_ascii_whitespace() {
+        let Some((key, value)) = option.split_once('=') else { continue };
+        let Some(level) = Level::from_name(value) else { continue };
+        if key == "loglevel" {
+            set_max_level(level);
+        } else if let Some(module) = key.strip_prefix("log.") {
+            set_module_level(module, level);
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, module_path: &'static str, args: fmt::Arguments) {
+    if !enabled(level, module_path) {
+        return;
+    }
+    let mut record = Record { level, timestamp_ns: timer::uptime_ns(), module: short_module(module_path), ..Record::EMPTY };
+    let mut text = TextBuffer { buf: &mut record.text, len: 0 };
+    let _ = text.write_fmt(args);
+    record.len = text.len;
+
+    irq::without_interrupts(|| unsafe {
+        RECORDS[WRITTEN % LOG_CAPACITY] = record;
+        WRITTEN += 1;
+        if level <= CONSOLE_LEVEL {
+            console::_print(level.color(), format_args!("{}\n", record));
+        }
+    });
+}
+
+// Hand every kept record to `f`, oldest first
+pub fn replay(mut f: impl FnMut(&Record)) {
+    irq::without_interrupts(|| unsafe {
+        let written = WRITTEN;
+        for seq in written.saturating_sub(LOG_CAPACITY)..written {
+            f(&(*core::ptr::addr_of!(RECORDS))[seq % LOG_CAPACITY]);
+        }
+    });
+}
+
+// Print the whole kept log to the console
+pub fn dmesg() {
+    replay(|record| console::_print(record.level.color(), format_args!("{}\n", record)));
+}
+
+// Records that no longer fit and were overwritten
+pub fn lost_records() -> usize {
+    unsafe { WRITTEN.saturating_sub(LOG_CAPACITY) }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => ($crate::log::_log($level, module_path!(), format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Trace, $($arg)*));
+}
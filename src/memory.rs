@@ -0,0 +1,215 @@
+// --- Physical frame allocator ---
+// One bit per 4 KiB frame, set when the frame is free. The bitmap is seeded
+// from the Multiboot2 memory map: everything starts out used, the available
+// areas are released, and then the regions the kernel already occupies are
+// claimed again so nothing handed out can overlap them.
+
+use crate::multiboot::{BootInfo, MemoryAreaType};
+
+pub const FRAME_SIZE: usize = 4096;
+
+// The bitmap covers the first 4 GiB of physical memory
+const MAX_PHYS_MEMORY: usize = 4 * 1024 * 1024 * 1024;
+const MAX_FRAMES: usize = MAX_PHYS_MEMORY / FRAME_SIZE;
+
+// Fixed low-memory regions set up before Rust runs (see boot.asm)
+const BOOT_PAGE_TABLES: (usize, usize) = (0x8000, 0xB000);
+pub const BOOT_STACK: (usize, usize) = (0x80000, 0x90000);
+const VGA_HOLE: (usize, usize) = (0xA0000, 0x100000);
+
+extern "C" {
+    static __kernel_start: u8;
+    static __kernel_end: u8;
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Frame(usize);
+
+impl Frame {
+    pub fn containing_address(addr: usize) -> Frame {
+        Frame(addr / FRAME_SIZE)
+    }
+
+    pub fn number(&self) -> usize {
+        self.0
+    }
+
+    pub fn start_address(&self) -> usize {
+        self.0 * FRAME_SIZE
+    }
+}
+
+// Zero-initialised so it lives in .bss and every frame starts out used
+static mut FRAME_BITMAP: [u64; MAX_FRAMES / 64] = [0; MAX_FRAMES / 64];
+static mut TOTAL_FRAMES: usize = 0;
+static mut FREE_FRAMES: usize = 0;
+// End of the highest usable area, used to size the identity map
+static mut HIGHEST_ADDRESS: usize = 0;
+// Lowest frame number that might be free, to keep searches short
+static mut NEXT_FREE_HINT: usize = 0;
+
+unsafe fn frame_is_used(frame: usize) -> bool {
+    FRAME_BITMAP[frame / 64] & (1 << (frame % 64)) == 0
+}
+
+unsafe fn mark_used(frame: usize) {
+    if !frame_is_used(frame) {
+        FRAME_BITMAP[frame / 64] &= !(1 << (frame % 64));
+        FREE_FRAMES -= 1;
+    }
+}
+
+unsafe fn mark_free(frame: usize) {
+    if frame_is_used(frame) {
+        FRAME_BITMAP[frame / 64] |= 1 << (frame % 64);
+        FREE_FRAMES += 1;
+        if frame < NEXT_FREE_HINT {
+            NEXT_FREE_HINT = frame;
+        }
+    }
+}
+
+// Release every whole frame inside [start, end)
+unsafe fn release_range(start: usize, end: usize) {
+    let first = start.div_ceil(FRAME_SIZE);
+    let last = (end / FRAME_SIZE).min(MAX_FRAMES);
+    for frame in first..last {
+        if frame_is_used(frame) {
+            TOTAL_FRAMES += 1;
+            mark_free(frame);
+        }
+    }
+    if last > first {
+        HIGHEST_ADDRESS = HIGHEST_ADDRESS.max(last * FRAME_SIZE);
+    }
+}
+
+// Claim every frame touching [start, end)
+unsafe fn reserve_range(start: usize, end: usize) {
+    let first = start / FRAME_SIZE;
+    let last = end.div_ceil(FRAME_SIZE).min(MAX_FRAMES);
+    for frame in first..last {
+        mark_used(frame);
+    }
+}
+
+pub fn kernel_image() -> (usize, usize) {
+    unsafe {
+        (
+            &__kernel_start as *const u8 as usize,
+            &__kernel_end as *const u8 as usize,
+        )
+    }
+}
+
+pub fn init(boot_info: Option<&BootInfo>) {
+    unsafe {
+        FRAME_BITMAP.fill(0);
+        TOTAL_FRAMES = 0;
+        FREE_FRAMES = 0;
+        HIGHEST_ADDRESS = 0;
+        NEXT_FREE_HINT = 0;
+
+        let Some(info) = boot_info else { return };
+        match info.memory_map() {
+            Some(map) => {
+                for area in map.filter(|area| area.typ == MemoryAreaType::Available) {
+                    let end = area.end().min(MAX_PHYS_MEMORY as u64) as usize;
+                    if (area.base as usize) < end {
+                        release_range(area.base as usize, end);
+                    }
+                }
+            }
+            None => {
+                // Old loaders only give us the amount of memory above 1 MiB
+                if let Some((_, upper_kib)) = info.basic_memory() {
+                    release_range(0x100000, 0x100000 + upper_kib as usize * 1024);
+                }
+            }
+        }
+
+        // Frame 0 stays unused so a null physical address is never valid
+        reserve_range(0, FRAME_SIZE);
+        reserve_range(BOOT_PAGE_TABLES.0, BOOT_PAGE_TABLES.1);
+        reserve_range(BOOT_STACK.0, BOOT_STACK.1);
+        reserve_range(VGA_HOLE.0, VGA_HOLE.1);
+        let (kernel_start, kernel_end) = kernel_image();
+        reserve_range(kernel_start, kernel_end);
+        reserve_range(info.start_address(), info.end_address());
+        for module in info.modules() {
+            reserve_range(module.start as usize, module.end as usize);
+        }
+    }
+}
+
+pub fn allocate_frame() -> Option<Frame> {
+    allocate_contiguous(1)
+}
+
+// Find `count` consecutive free frames, lowest addresses first
+pub fn allocate_contiguous(count: usize) -> Option<Frame> {
+    if count == 0 {
+        return None;
+    }
+    unsafe {
+        if FREE_FRAMES < count {
+            return None;
+        }
+        let mut run_start = NEXT_FREE_HINT;
+        let mut run_len = 0;
+        let mut frame = NEXT_FREE_HINT;
+        while frame < MAX_FRAMES {
+            // Skip fully used words quickly
+            if frame % 64 == 0 && FRAME_BITMAP[frame / 64] == 0 {
+                frame += 64;
+                run_len = 0;
+                continue;
+            }
+            if frame_is_used(frame) {
+                run_len = 0;
+            } else {
+                if run_len == 0 {
+                    run_start = frame;
+                }
+                run_len += 1;
+                if run_len == count {
+                    for f in run_start..run_start + count {
+                        mark_used(f);
+                    }
+                    if run_start == NEXT_FREE_HINT {
+                        NEXT_FREE_HINT = run_start + count;
+                    }
+                    return Some(Frame(run_start));
+                }
+            }
+            frame += 1;
+        }
+        None
+    }
+}
+
+pub fn free_frame(frame: Frame) {
+    free_contiguous(frame, 1);
+}
+
+pub fn free_contiguous(first: Frame, count: usize) {
+    unsafe {
+        let end = (first.0 + count).min(MAX_FRAMES);
+        for frame in first.0..end {
+            mark_free(frame);
+        }
+    }
+}
+
+// Usable RAM known to the allocator, in bytes
+pub fn total_memory() -> usize {
+    unsafe { TOTAL_FRAMES * FRAME_SIZE }
+}
+
+pub fn free_memory() -> usize {
+    unsafe { FREE_FRAMES * FRAME_SIZE }
+}
+
+pub fn highest_address() -> usize {
+    unsafe { HIGHEST_ADDRESS }
+}
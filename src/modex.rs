@@ -0,0 +1,219 @@
+// --- Mode X: unchained 320x240 in 256 colors ---
+// Mode 13h with chain-4 turned off and 480-line timing: square pixels, and
+// all 256 KB of video memory become reachable. Pixel x of a row lives in
+// plane x % 4 at byte x / 4, so a row is 80 bytes in each plane and a
+// screen takes 19200 bytes per plane; three such pages fit. The CRTC start
+// address picks the page shown, so double buffering is a page flip instead
+// of a copy. Pages 0 and 1 are flipped between; page 2 is off-screen
+// storage, e.g. a background to restore with copy_page.
+//
+// Writes select their planes with the sequencer map mask, so one byte
+// store can set the same color in up to four neighbouring pixels. Copies
+// use write mode 1: reading a byte loads all four planes into the latches
+// and a write stores them, moving four pixels per access.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use crate::vga::{self, write_crtc, write_graphics, write_sequencer};
+
+pub const WIDTH: usize = 320;
+pub const HEIGHT: usize = 240;
+pub const PAGES: usize = 3;
+const PITCH: usize = WIDTH / 4;
+const PAGE_SIZE: usize = PITCH * HEIGHT;
+const VGA_MEMORY: *mut u8 = 0xA0000 as *mut u8;
+
+const SEQ_MAP_MASK: u8 = 0x02;
+const GC_READ_MAP: u8 = 0x04;
+const GC_MODE: u8 = 0x05;
+const CRTC_START_HIGH: u8 = 0x0C;
+const CRTC_START_LOW: u8 = 0x0D;
+
+const ALL_PLANES: u8 = 0x0F;
+// Keeps the 256-color shift mode set in Mode X's graphics mode register
+const WRITE_MODE_0: u8 = 0x40;
+const WRITE_MODE_1: u8 = 0x41;
+
+static mut VISIBLE_PAGE: usize = 0;
+static mut DRAW_PAGE: usize = 0;
+
+// Show page 0 and draw on it; called after the mode's registers are loaded
+pub fn reset() {
+    unsafe {
+        VISIBLE_PAGE = 0;
+        DRAW_PAGE = 0;
+    }
+    set_start_address(0);
+}
+
+pub fn visible_page() -> usize {
+    unsafe { VISIBLE_PAGE }
+}
+
+pub fn draw_page() -> usize {
+    unsafe { DRAW_PAGE }
+}
+
+pub fn set_draw_page(page: usize) {
+    unsafe { DRAW_PAGE = page.min(PAGES - 1); }
+}
+
+fn set_start_address(offset: usize) {
+    write_crtc(CRTC_START_HIGH, (offset >> 8) as u8);
+    write_crtc(CRTC_START_LOW, offset as u8);
+}
+
+// Display `page`. The CRTC latches the start address when vertical
+// retrace begins, so wait for that before anything draws on the old page.
+pub fn show_page(page: usize) {
+    let page = page.min(PAGES - 1);
+    // Don't change the address halfway through a retrace
+    vga::wait_for_display();
+    set_start_address(page * PAGE_SIZE);
+    vga::wait_for_vblank();
+    unsafe { VISIBLE_PAGE = page; }
+}
+
+// Show the page just drawn and draw the next frame on the other one
+pub fn flip() {
+    let shown = draw_page();
+    show_page(shown);
+    set_draw_page(1 - shown.min(1));
+}
+
+fn select_planes(mask: u8) {
+    write_sequencer(SEQ_MAP_MASK, mask);
+}
+
+// Start of `page` in video memory. Pages past the last are clamped, so a bad
+// page number can't write beyond the 64 KB window into the text buffer.
+fn page_base(page: usize) -> usize {
+    page.min(PAGES - 1) * PAGE_SIZE
+}
+
+fn offset(page: usize, x: usize, y: usize) -> usize {
+    page_base(page) + y * PITCH + x / 4
+}
+
+pub fn set_pixel(page: usize, x: usize, y: usize, color: u8) {
+    if x >= WIDTH || y >= HEIGHT {
+        return;
+    }
+    select_planes(1 << (x & 3));
+    unsafe { write_volatile(VGA_MEMORY.add(offset(page, x, y)), color); }
+    select_planes(ALL_PLANES);
+}
+
+pub fn get_pixel(page: usize, x: usize, y: usize) -> u8 {
+    if x >= WIDTH || y >= HEIGHT {
+        return 0;
+    }
+    write_graphics(GC_READ_MAP, (x & 3) as u8);
+    unsafe { read_volatile(VGA_MEMORY.add(offset(page, x, y))) }
+}
+
+pub fn fill_rect(page: usize, x: usize, y: usize, w: usize, h: usize, color: u8) {
+    let end_x = x.saturating_add(w).min(WIDTH);
+    let end_y = y.saturating_add(h).min(HEIGHT);
+    if x >= end_x || y >= end_y {
+        return;
+    }
+    let base = page_base(page);
+    let (first, last) = (x / 4, (end_x - 1) / 4);
+    let left = (ALL_PLANES << (x & 3)) & ALL_PLANES;
+    let right = ALL_PLANES >> (3 - ((end_x - 1) & 3));
+    // Column by column, so the map mask changes at most three times
+    let column = |byte: usize, mask: u8| {
+        select_planes(mask);
+        for row in y..end_y {
+            unsafe { write_volatile(VGA_MEMORY.add(base + row * PITCH + byte), color); }
+        }
+    };
+    if first == last {
+        column(first, left & right);
+    } else {
+        column(first, left);
+        select_planes(ALL_PLANES);
+        for row in y..end_y {
+            for byte in first + 1..last {
+                unsafe { write_volatile(VGA_MEMORY.add(base + row * PITCH + byte), color); }
+            }
+        }
+        column(last, right);
+    }
+    select_planes(ALL_PLANES);
+}
+
+pub fn clear(page: usize, color: u8) {
+    fill_rect(page, 0, 0, WIDTH, HEIGHT, color);
+}
+
+// Set the pixels of a 1bpp bitmap (see crate::bitmap_bit) in `color`, one
+// plane at a time
+pub fn draw_bitmap(page: usize, x: usize, y: usize, w: usize, h: usize, bitmap: &[u8], color: u8) {
+    let (visible_w, visible_h) = crate::clip_area(x, y, w, h);
+    for plane in 0..4 {
+        select_planes(1 << plane);
+        // First column of the bitmap that falls in this plane
+        let start = (plane + 4 - (x & 3)) & 3;
+        for row in 0..visible_h {
+            for col in (start..visible_w).step_by(4) {
+                if crate::bitmap_bit(bitmap, w, col, row) {
+                    unsafe { write_volatile(VGA_MEMORY.add(offset(page, x + col, y + row)), color); }
+                }
+            }
+        }
+    }
+    select_planes(ALL_PLANES);
+}
+
+// Copy `bytes` four-pixel groups from `src` to `dst` through the latches;
+// the caller has selected write mode 1
+fn copy_bytes(src: usize, dst: usize, bytes: usize, backwards: bool) {
+    for i in 0..bytes {
+        let i = if backwards { bytes - 1 - i } else { i };
+        unsafe {
+            read_volatile(VGA_MEMORY.add(src + i));
+            write_volatile(VGA_MEMORY.add(dst + i), 0);
+        }
+    }
+}
+
+// Copy an area of a page already clipped by fb_blit, in
+// crate::copy_direction order. Copies on four-pixel boundaries go through
+// the latches.
+pub fn copy_rect(page: usize, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, w: usize, h: usize) {
+    let (rows_up, cols_back) = crate::copy_direction(src_x, src_y, dst_x, dst_y);
+    let row_at = |i: usize| if rows_up { h - 1 - i } else { i };
+    if src_x % 4 == 0 && dst_x % 4 == 0 && w % 4 == 0 {
+        let (src_start, dst_start) = (offset(page, src_x, src_y), offset(page, dst_x, dst_y));
+        select_planes(ALL_PLANES);
+        write_graphics(GC_MODE, WRITE_MODE_1);
+        for i in 0..h {
+            let row = row_at(i);
+            copy_bytes(src_start + row * PITCH, dst_start + row * PITCH, w / 4, cols_back);
+        }
+        write_graphics(GC_MODE, WRITE_MODE_0);
+    } else {
+        for i in 0..h {
+            let row = row_at(i);
+            for j in 0..w {
+                let col = if cols_back { w - 1 - j } else { j };
+                let color = get_pixel(page, src_x + col, src_y + row);
+                set_pixel(page, dst_x + col, dst_y + row, color);
+            }
+        }
+    }
+}
+
+// Copy a whole page, e.g. to restore a background kept on page 2
+pub fn copy_page(src: usize, dst: usize) {
+    let (src, dst) = (src.min(PAGES - 1), dst.min(PAGES - 1));
+    if src == dst {
+        return;
+    }
+    select_planes(ALL_PLANES);
+    write_graphics(GC_MODE, WRITE_MODE_1);
+    copy_bytes(src * PAGE_SIZE, dst * PAGE_SIZE, PAGE_SIZE, false);
+    write_graphics(GC_MODE, WRITE_MODE_0);
+}
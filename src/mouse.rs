@@ -0,0 +1,150 @@
+// --- PS/2 mouse ---
+// The mouse sits on the controller's second port and reports through IRQ12.
+// Like the keyboard, the interrupt handler only queues raw bytes; packets are
+// assembled by the reader, which resynchronises on the always-set bit 3 of
+// the first byte if a byte ever gets lost. IntelliMouse wheels are switched
+// on with the usual sample-rate knock and send a fourth byte per packet.
+
+use crate::interrupts::InterruptFrame;
+use crate::irq;
+use crate::ps2::{self, Device, Port};
+use crate::ringbuf::RingBuffer;
+use crate::inb;
+
+const PS2_DATA: u16 = 0x60;
+const PS2_STATUS: u16 = 0x64;
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+// Set when the byte in the output buffer came from the second port
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const CMD_SET_DEFAULTS: u8 = 0xF6;
+const CMD_SET_SAMPLE_RATE: u8 = 0xF3;
+const CMD_IDENTIFY: u8 = 0xF2;
+const CMD_ENABLE_REPORTING: u8 = 0xF4;
+
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+
+pub const BUTTON_LEFT: u8 = 1 << 0;
+pub const BUTTON_RIGHT: u8 = 1 << 1;
+pub const BUTTON_MIDDLE: u8 = 1 << 2;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MouseEvent {
+    // Motion in mouse counts; dy grows downwards like screen coordinates
+    pub dx: i16,
+    pub dy: i16,
+    // Positive when the wheel is turned towards the user
+    pub wheel: i8,
+    // BUTTON_* bits currently held
+    pub buttons: u8,
+}
+
+static BYTES: RingBuffer<256> = RingBuffer::new();
+static mut PACKET_SIZE: usize = 3;
+static mut PACKET: [u8; 4] = [0; 4];
+static mut PACKET_LEN: usize = 0;
+
+fn mouse_interrupt(_frame: &mut InterruptFrame) {
+    unsafe {
+        let status = inb(PS2_STATUS);
+        if status & STATUS_OUTPUT_FULL != 0 && status & STATUS_AUX_DATA != 0 {
+            queue_byte(inb(PS2_DATA));
+        }
+    }
+}
+
+fn queue_byte(byte: u8) {
+    BYTES.push(byte);
+}
+
+fn set_sample_rate(rate: u8) -> bool {
+    ps2::send_command(Port::Second, CMD_SET_SAMPLE_RATE).is_ok()
+        && ps2::send_command(Port::Second, rate).is_ok()
+}
+
+// Knock 200, 100, 80 on the sample rate; a wheel mouse then reports ID 3
+fn enable_wheel() -> bool {
+    if !(set_sample_rate(200) && set_sample_rate(100) && set_sample_rate(80)) {
+        return false;
+    }
+    if ps2::send_command(Port::Second, CMD_IDENTIFY).is_err() {
+        return false;
+    }
+    ps2::read_data() == Some(0x03)
+}
+
+// Requires ps2::init() to have found a mouse on the second port. Returns
+// false if there is none or it does not respond.
+pub fn init() -> bool {
+    match ps2::device(Port::Second) {
+        Device::Mouse | Device::WheelMouse | Device::FiveButtonMouse => {}
+        _ => return false,
+    }
+    if ps2::send_command(Port::Second, CMD_SET_DEFAULTS).is_err() {
+        return false;
+    }
+    let wheel = enable_wheel();
+    // The knock leaves the rate at 80 samples per second
+    set_sample_rate(100);
+    unsafe {
+        PACKET_SIZE = if wheel { 4 } else { 3 };
+    }
+    // Bytes that arrive while the keyboard driver polls for an ACK
+    ps2::set_aux_handler(queue_byte);
+    if ps2::send_command(Port::Second, CMD_ENABLE_REPORTING).is_err() {
+        return false;
+    }
+    irq::register_handler(irq::IRQ_MOUSE, mouse_interrupt)
+}
+
+pub fn has_wheel() -> bool {
+    unsafe { PACKET_SIZE == 4 }
+}
+
+pub fn has_data() -> bool {
+    !BYTES.is_empty()
+}
+
+fn decode(packet: [u8; 4], size: usize) -> MouseEvent {
+    let flags = packet[0];
+    // 9-bit two's complement with the sign bit in the first byte; an
+    // overflowed axis carries garbage and is dropped
+    let axis = |value: u8, sign: u8, overflow: u8| -> i16 {
+        if flags & overflow != 0 {
+            0
+        } else if flags & sign != 0 {
+            value as i16 - 256
+        } else {
+            value as i16
+        }
+    };
+    let dx = axis(packet[1], PACKET_X_SIGN, PACKET_X_OVERFLOW);
+    let dy = axis(packet[2], PACKET_Y_SIGN, PACKET_Y_OVERFLOW);
+    // The wheel delta is a 4-bit signed value
+    let wheel = if size == 4 { ((packet[3] << 4) as i8) >> 4 } else { 0 };
+    MouseEvent { dx, dy: -dy, wheel, buttons: flags & (BUTTON_LEFT | BUTTON_RIGHT | BUTTON_MIDDLE) }
+}
+
+// Assemble the next complete packet from the queued bytes, if there is one
+pub fn try_read_event() -> Option<MouseEvent> {
+    unsafe {
+        let size = PACKET_SIZE;
+        while let Some(byte) = BYTES.pop() {
+            if PACKET_LEN == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+                // Out of sync; skip until something that looks like a header
+                continue;
+            }
+            PACKET[PACKET_LEN] = byte;
+            PACKET_LEN += 1;
+            if PACKET_LEN == size {
+                PACKET_LEN = 0;
+                return Some(decode(PACKET, size));
+            }
+        }
+    }
+    None
+}
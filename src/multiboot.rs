@@ -0,0 +1,347 @@
+// --- Multiboot2 boot information parser ---
+// GRUB leaves a pointer to the boot information block in ebx. The block is a
+// u32 total size, a reserved u32 and then a list of 8-byte aligned tags, each
+// starting with a u32 type and a u32 size. Every read below is checked
+// against the total size so a truncated or corrupt block cannot walk us off
+// into random memory.
+
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_BOOT_LOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_BASIC_MEMINFO: u32 = 4;
+const TAG_MMAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ELF_SECTIONS: u32 = 9;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+
+// Upper bound on what we are willing to believe about the block size
+const MAX_INFO_SIZE: usize = 1024 * 1024;
+
+fn read_u8(bytes: &[u8], off: usize) -> Option<u8> {
+    bytes.get(off).copied()
+}
+
+fn read_u32(bytes: &[u8], off: usize) -> Option<u32> {
+    let b = bytes.get(off..off + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(bytes: &[u8], off: usize) -> Option<u64> {
+    let b = bytes.get(off..off + 8)?;
+    let mut raw = [0u8; 8];
+    raw.copy_from_slice(b);
+    Some(u64::from_le_bytes(raw))
+}
+
+// Interpret a NUL-terminated string stored in a tag payload
+fn read_cstr(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).unwrap_or("")
+}
+
+#[derive(Copy, Clone)]
+pub struct BootInfo {
+    bytes: &'static [u8],
+}
+
+#[derive(Copy, Clone)]
+pub struct Tag {
+    pub typ: u32,
+    // Payload following the 8-byte tag header
+    pub data: &'static [u8],
+}
+
+pub struct TagIter {
+    bytes: &'static [u8],
+    offset: usize,
+}
+
+impl Iterator for TagIter {
+    type Item = Tag;
+
+    fn next(&mut self) -> Option<Tag> {
+        let typ = read_u32(self.bytes, self.offset)?;
+        let size = read_u32(self.bytes, self.offset + 4)? as usize;
+        if typ == TAG_END || size < 8 {
+            return None;
+        }
+        let data = self.bytes.get(self.offset + 8..self.offset + size)?;
+        // Tags are padded so that the next one starts 8-byte aligned
+        self.offset += (size + 7) & !7;
+        Some(Tag { typ, data })
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum MemoryAreaType {
+    Available,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    Defective,
+    Other(u32),
+}
+
+impl MemoryAreaType {
+    fn from_raw(raw: u32) -> MemoryAreaType {
+        match raw {
+            1 => MemoryAreaType::Available,
+            2 => MemoryAreaType::Reserved,
+            3 => MemoryAreaType::AcpiReclaimable,
+            4 => MemoryAreaType::AcpiNvs,
+            5 => MemoryAreaType::Defective,
+            n => MemoryAreaType::Other(n),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct MemoryArea {
+    pub base: u64,
+    pub length: u64,
+    pub typ: MemoryAreaType,
+}
+
+impl MemoryArea {
+    pub fn end(&self) -> u64 {
+        self.base.saturating_add(self.length)
+    }
+}
+
+pub struct MemoryMapIter {
+    entries: &'static [u8],
+    entry_size: usize,
+    offset: usize,
+}
+
+impl Iterator for MemoryMapIter {
+    type Item = MemoryArea;
+
+    fn next(&mut self) -> Option<MemoryArea> {
+        let entry = self.entries.get(self.offset..self.offset + self.entry_size)?;
+        self.offset += self.entry_size;
+        Some(MemoryArea {
+            base: read_u64(entry, 0)?,
+            length: read_u64(entry, 8)?,
+            typ: MemoryAreaType::from_raw(read_u32(entry, 16)?),
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Module {
+    pub start: u32,
+    pub end: u32,
+    pub cmdline: &'static str,
+}
+
+pub struct ModuleIter {
+    tags: TagIter,
+}
+
+impl Iterator for ModuleIter {
+    type Item = Module;
+
+    fn next(&mut self) -> Option<Module> {
+        for tag in self.tags.by_ref() {
+            if tag.typ != TAG_MODULE {
+                continue;
+            }
+            let start = read_u32(tag.data, 0)?;
+            let end = read_u32(tag.data, 4)?;
+            let cmdline = read_cstr(tag.data.get(8..)?);
+            return Some(Module { start, end, cmdline });
+        }
+        None
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum FramebufferType {
+    Indexed,
+    Rgb,
+    EgaText,
+    Other(u8),
+}
+
+#[derive(Copy, Clone)]
+pub struct FramebufferInfo {
+    pub addr: u64,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+    pub typ: FramebufferType,
+}
+
+#[derive(Copy, Clone)]
+pub struct ElfSection {
+    pub name_index: u32,
+    pub typ: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+}
+
+pub struct ElfSectionIter {
+    headers: &'static [u8],
+    entry_size: usize,
+    remaining: usize,
+    offset: usize,
+}
+
+impl Iterator for ElfSectionIter {
+    type Item = ElfSection;
+
+    fn next(&mut self) -> Option<ElfSection> {
+        // 64-bit section headers are 64 bytes; anything smaller is not ELF64
+        if self.remaining == 0 || self.entry_size < 64 {
+            return None;
+        }
+        let hdr = self.headers.get(self.offset..self.offset + self.entry_size)?;
+        self.offset += self.entry_size;
+        self.remaining -= 1;
+        Some(ElfSection {
+            name_index: read_u32(hdr, 0)?,
+            typ: read_u32(hdr, 4)?,
+            flags: read_u64(hdr, 8)?,
+            addr: read_u64(hdr, 16)?,
+            offset: read_u64(hdr, 24)?,
+            size: read_u64(hdr, 32)?,
+        })
+    }
+}
+
+// Copy of the ACPI RSDP that the boot loader placed inside the info block
+#[derive(Copy, Clone)]
+pub struct Rsdp {
+    pub revision: u8,
+    pub rsdt_addr: u32,
+    pub xsdt_addr: Option<u64>,
+    pub bytes: &'static [u8],
+}
+
+impl BootInfo {
+    // Validate the block header and build a bounds-checked view over it.
+    // The caller guarantees that `addr` is identity-mapped.
+    pub unsafe fn load(magic: u32, addr: usize) -> Option<BootInfo> {
+        if magic != BOOTLOADER_MAGIC || addr == 0 || addr & 7 != 0 {
+            return None;
+        }
+        let total_size = *(addr as *const u32) as usize;
+        if !(16..=MAX_INFO_SIZE).contains(&total_size) {
+            return None;
+        }
+        let bytes = core::slice::from_raw_parts(addr as *const u8, total_size);
+        Some(BootInfo { bytes })
+    }
+
+    pub fn start_address(&self) -> usize {
+        self.bytes.as_ptr() as usize
+    }
+
+    pub fn end_address(&self) -> usize {
+        self.start_address() + self.bytes.len()
+    }
+
+    pub fn tags(&self) -> TagIter {
+        TagIter { bytes: self.bytes, offset: 8 }
+    }
+
+    fn find_tag(&self, typ: u32) -> Option<Tag> {
+        self.tags().find(|tag| tag.typ == typ)
+    }
+
+    pub fn command_line(&self) -> Option<&'static str> {
+        self.find_tag(TAG_CMDLINE).map(|tag| read_cstr(tag.data))
+    }
+
+    pub fn boot_loader_name(&self) -> Option<&'static str> {
+        self.find_tag(TAG_BOOT_LOADER_NAME).map(|tag| read_cstr(tag.data))
+    }
+
+    // Lower and upper memory in KiB as reported by the BIOS
+    pub fn basic_memory(&self) -> Option<(u32, u32)> {
+        let tag = self.find_tag(TAG_BASIC_MEMINFO)?;
+        Some((read_u32(tag.data, 0)?, read_u32(tag.data, 4)?))
+    }
+
+    pub fn memory_map(&self) -> Option<MemoryMapIter> {
+        let tag = self.find_tag(TAG_MMAP)?;
+        let entry_size = read_u32(tag.data, 0)? as usize;
+        if entry_size < 24 {
+            return None;
+        }
+        Some(MemoryMapIter { entries: tag.data.get(8..)?, entry_size, offset: 0 })
+    }
+
+    pub fn modules(&self) -> ModuleIter {
+        ModuleIter { tags: self.tags() }
+    }
+
+    pub fn framebuffer(&self) -> Option<FramebufferInfo> {
+        let tag = self.find_tag(TAG_FRAMEBUFFER)?;
+        let typ = match read_u8(tag.data, 21)? {
+            0 => FramebufferType::Indexed,
+            1 => FramebufferType::Rgb,
+            2 => FramebufferType::EgaText,
+            n => FramebufferType::Other(n),
+        };
+        Some(FramebufferInfo {
+            addr: read_u64(tag.data, 0)?,
+            pitch: read_u32(tag.data, 8)?,
+            width: read_u32(tag.data, 12)?,
+            height: read_u32(tag.data, 16)?,
+            bpp: read_u8(tag.data, 20)?,
+            typ,
+        })
+    }
+
+    pub fn elf_sections(&self) -> Option<ElfSectionIter> {
+        let tag = self.find_tag(TAG_ELF_SECTIONS)?;
+        let num = read_u32(tag.data, 0)? as usize;
+        let entry_size = read_u32(tag.data, 4)? as usize;
+        Some(ElfSectionIter { headers: tag.data.get(12..)?, entry_size, remaining: num, offset: 0 })
+    }
+
+    // Prefer the ACPI 2.0+ RSDP when the loader supplied both
+    pub fn rsdp(&self) -> Option<Rsdp> {
+        let tag = self.find_tag(TAG_ACPI_NEW).or_else(|| self.find_tag(TAG_ACPI_OLD))?;
+        let bytes = tag.data;
+        if bytes.get(0..8)? != b"RSD PTR " {
+            return None;
+        }
+        let revision = read_u8(bytes, 15)?;
+        let rsdt_addr = read_u32(bytes, 16)?;
+        let xsdt_addr = if revision >= 2 { read_u64(bytes, 24) } else { None };
+        Some(Rsdp { revision, rsdt_addr, xsdt_addr, bytes })
+    }
+
+    // Sum of all usable RAM in bytes according to the memory map
+    pub fn usable_memory(&self) -> u64 {
+        match self.memory_map() {
+            Some(map) => map
+                .filter(|area| area.typ == MemoryAreaType::Available)
+                .map(|area| area.length)
+                .sum(),
+            None => self.basic_memory().map_or(0, |(lower, upper)| (lower as u64 + upper as u64) * 1024),
+        }
+    }
+}
+
+// Boot information saved by _start for the rest of the kernel
+static mut BOOT_INFO: Option<BootInfo> = None;
+
+pub unsafe fn init(magic: u32, addr: usize) -> Option<BootInfo> {
+    BOOT_INFO = BootInfo::load(magic, addr);
+    BOOT_INFO
+}
+
+pub fn boot_info() -> Option<BootInfo> {
+    unsafe { BOOT_INFO }
+}
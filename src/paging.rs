@@ -0,0 +1,422 @@
+// --- Four-level page table manager ---
+// boot.asm only identity-maps the first 1 GiB with 2 MiB pages from a fixed
+// PML4 at 0x8000. init() replaces that with tables allocated from the frame
+// allocator which identity-map all physical memory the allocator knows about.
+// Page tables are always reached through that identity map, so table frames
+// must come from memory that is mapped at the time they are allocated.
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+use core::ops::BitOr;
+
+use crate::memory::{self, Frame, FRAME_SIZE};
+use crate::{rdmsr, wrmsr};
+
+pub const PAGE_SIZE_4K: usize = 4096;
+pub const PAGE_SIZE_2M: usize = 2 * 1024 * 1024;
+
+const ENTRIES_PER_TABLE: usize = 512;
+const ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+// The boot tables map this much; table frames must be below it until init()
+const BOOT_IDENTITY_LIMIT: usize = 1024 * 1024 * 1024;
+// Physical memory identity-mapped by init(), matching the frame allocator
+const IDENTITY_MAP_LIMIT: usize = 4 * 1024 * 1024 * 1024;
+// Page tables built by boot.asm, released once init() has switched away
+const BOOT_TABLES: usize = 0x8000;
+const BOOT_TABLE_COUNT: usize = 3;
+
+const IA32_EFER: u32 = 0xC000_0080;
+const EFER_NXE: u64 = 1 << 11;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct PageFlags(u64);
+
+impl PageFlags {
+    pub const NONE: PageFlags = PageFlags(0);
+    pub const PRESENT: PageFlags = PageFlags(1 << 0);
+    pub const WRITABLE: PageFlags = PageFlags(1 << 1);
+    pub const USER: PageFlags = PageFlags(1 << 2);
+    pub const WRITE_THROUGH: PageFlags = PageFlags(1 << 3);
+    pub const NO_CACHE: PageFlags = PageFlags(1 << 4);
+    pub const ACCESSED: PageFlags = PageFlags(1 << 5);
+    pub const DIRTY: PageFlags = PageFlags(1 << 6);
+    pub const HUGE: PageFlags = PageFlags(1 << 7);
+    pub const GLOBAL: PageFlags = PageFlags(1 << 8);
+    pub const NO_EXECUTE: PageFlags = PageFlags(1 << 63);
+
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(&self, other: PageFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for PageFlags {
+    type Output = PageFlags;
+
+    fn bitor(self, rhs: PageFlags) -> PageFlags {
+        PageFlags(self.0 | rhs.0)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum PageSize {
+    Size4K,
+    Size2M,
+}
+
+impl PageSize {
+    pub fn bytes(&self) -> usize {
+        match self {
+            PageSize::Size4K => PAGE_SIZE_4K,
+            PageSize::Size2M => PAGE_SIZE_2M,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MapError {
+    OutOfFrames,
+    AlreadyMapped,
+    NotMapped,
+    Misaligned,
+    // A 2 MiB mapping was requested where 4 KiB pages already exist
+    TableInTheWay,
+}
+
+// Result of walking the tables for a virtual address
+#[derive(Copy, Clone)]
+pub struct Mapping {
+    pub phys: usize,
+    pub size: PageSize,
+    pub flags: PageFlags,
+}
+
+static mut KERNEL_PML4: usize = 0;
+static mut NX_ENABLED: bool = false;
+
+fn read_cr3() -> usize {
+    let value: u64;
+    unsafe { asm!("mov {}, cr3", out(reg) value, options(nomem, nostack, preserves_flags)); }
+    (value & ADDR_MASK) as usize
+}
+
+unsafe fn write_cr3(pml4: usize) {
+    asm!("mov cr3, {}", in(reg) pml4 as u64, options(nostack, preserves_flags));
+}
+
+pub fn flush(virt: usize) {
+    unsafe { asm!("invlpg [{}]", in(reg) virt, options(nostack, preserves_flags)); }
+}
+
+pub fn flush_all() {
+    unsafe { write_cr3(read_cr3()); }
+}
+
+unsafe fn table_at(phys: usize) -> &'static mut [u64; ENTRIES_PER_TABLE] {
+    &mut *(phys as *mut [u64; ENTRIES_PER_TABLE])
+}
+
+fn table_indices(virt: usize) -> [usize; 4] {
+    [
+        (virt >> 39) & 0x1FF,
+        (virt >> 30) & 0x1FF,
+        (virt >> 21) & 0x1FF,
+        (virt >> 12) & 0x1FF,
+    ]
+}
+
+fn entry_present(entry: u64) -> bool {
+    entry & PageFlags::PRESENT.bits() != 0
+}
+
+fn entry_huge(entry: u64) -> bool {
+    entry & PageFlags::HUGE.bits() != 0
+}
+
+fn entry_addr(entry: u64) -> usize {
+    (entry & ADDR_MASK) as usize
+}
+
+fn entry_flags(entry: u64) -> PageFlags {
+    PageFlags(entry & !ADDR_MASK)
+}
+
+// Only set NX in entries when EFER.NXE is on, otherwise it is a reserved bit
+fn sanitize(flags: PageFlags) -> u64 {
+    let bits = flags.bits() | PageFlags::PRESENT.bits();
+    if unsafe { NX_ENABLED } {
+        bits
+    } else {
+        bits & !PageFlags::NO_EXECUTE.bits()
+    }
+}
+
+fn allocate_table(limit: usize) -> Result<usize, MapError> {
+    let frame = memory::allocate_frame().ok_or(MapError::OutOfFrames)?;
+    let phys = frame.start_address();
+    if phys + FRAME_SIZE > limit {
+        memory::free_frame(frame);
+        return Err(MapError::OutOfFrames);
+    }
+    unsafe { table_at(phys).fill(0); }
+    Ok(phys)
+}
+
+fn table_limit() -> usize {
+    unsafe {
+        if KERNEL_PML4 == 0 { BOOT_IDENTITY_LIMIT } else { IDENTITY_MAP_LIMIT }
+    }
+}
+
+// Return the next-level table behind `table[index]`, creating it if missing.
+// Intermediate entries are kept permissive; the leaf decides the access.
+unsafe fn next_table(table: &mut [u64; ENTRIES_PER_TABLE], index: usize, user: bool) -> Result<usize, MapError> {
+    let entry = table[index];
+    if entry_present(entry) {
+        if entry_huge(entry) {
+            return Err(MapError::AlreadyMapped);
+        }
+        if user {
+            table[index] |= PageFlags::USER.bits();
+        }
+        return Ok(entry_addr(entry));
+    }
+    let phys = allocate_table(table_limit())?;
+    let mut flags = PageFlags::PRESENT | PageFlags::WRITABLE;
+    if user {
+        flags = flags | PageFlags::USER;
+    }
+    table[index] = phys as u64 | flags.bits();
+    Ok(phys)
+}
+
+// Replace a 2 MiB mapping by an equivalent table of 512 4 KiB pages
+unsafe fn split_huge_page(pd: &mut [u64; ENTRIES_PER_TABLE], index: usize) -> Result<usize, MapError> {
+    let entry = pd[index];
+    let base = entry_addr(entry) & !(PAGE_SIZE_2M - 1);
+    // PAT lives in bit 12 for huge pages and bit 7 for small ones; drop both
+    let flags = entry & !ADDR_MASK & !PageFlags::HUGE.bits();
+    let phys = allocate_table(table_limit())?;
+    let pt = table_at(phys);
+    for (i, slot) in pt.iter_mut().enumerate() {
+        *slot = (base + i * PAGE_SIZE_4K) as u64 | flags;
+    }
+    pd[index] = phys as u64 | (flags & (PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::USER).bits());
+    flush_all();
+    Ok(phys)
+}
+
+pub fn translate(virt: usize) -> Option<usize> {
+    walk(virt).map(|m| m.phys + (virt & (m.size.bytes() - 1)))
+}
+
+pub fn walk(virt: usize) -> Option<Mapping> {
+    let [i4, i3, i2, i1] = table_indices(virt);
+    unsafe {
+        let pml4e = table_at(read_cr3())[i4];
+        if !entry_present(pml4e) {
+            return None;
+        }
+        let pdpte = table_at(entry_addr(pml4e))[i3];
+        if !entry_present(pdpte) || entry_huge(pdpte) {
+            // 1 GiB pages are never created by this module
+            return None;
+        }
+        let pde = table_at(entry_addr(pdpte))[i2];
+        if !entry_present(pde) {
+            return None;
+        }
+        if entry_huge(pde) {
+            return Some(Mapping { phys: entry_addr(pde) & !(PAGE_SIZE_2M - 1), size: PageSize::Size2M, flags: entry_flags(pde) });
+        }
+        let pte = table_at(entry_addr(pde))[i1];
+        if !entry_present(pte) {
+            return None;
+        }
+        Some(Mapping { phys: entry_addr(pte), size: PageSize::Size4K, flags: entry_flags(pte) })
+    }
+}
+
+pub fn map_page(virt: usize, phys: usize, size: PageSize, flags: PageFlags) -> Result<(), MapError> {
+    if !virt.is_multiple_of(size.bytes()) || !phys.is_multiple_of(size.bytes()) {
+        return Err(MapError::Misaligned);
+    }
+    let [i4, i3, i2, i1] = table_indices(virt);
+    let user = flags.contains(PageFlags::USER);
+    unsafe {
+        let pdpt = next_table(table_at(read_cr3()), i4, user)?;
+        let pd = table_at(next_table(table_at(pdpt), i3, user)?);
+        match size {
+            PageSize::Size2M => {
+                if entry_present(pd[i2]) {
+                    return Err(if entry_huge(pd[i2]) { MapError::AlreadyMapped } else { MapError::TableInTheWay });
+                }
+                pd[i2] = phys as u64 | sanitize(flags | PageFlags::HUGE);
+            }
+            PageSize::Size4K => {
+                let pt = table_at(next_table(pd, i2, user)?);
+                if entry_present(pt[i1]) {
+                    return Err(MapError::AlreadyMapped);
+                }
+                pt[i1] = phys as u64 | sanitize(flags);
+            }
+        }
+    }
+    flush(virt);
+    Ok(())
+}
+
+// Locate the leaf entry for `virt`. With `split` set, a 2 MiB page covering
+// the address is broken up so the 4 KiB entry can be changed on its own.
+unsafe fn leaf_entry(virt: usize, split: bool) -> Result<(&'static mut u64, PageSize), MapError> {
+    let [i4, i3, i2, i1] = table_indices(virt);
+    let pml4 = table_at(read_cr3());
+    if !entry_present(pml4[i4]) {
+        return Err(MapError::NotMapped);
+    }
+    let pdpt = table_at(entry_addr(pml4[i4]));
+    if !entry_present(pdpt[i3]) || entry_huge(pdpt[i3]) {
+        return Err(MapError::NotMapped);
+    }
+    let pd = table_at(entry_addr(pdpt[i3]));
+    if !entry_present(pd[i2]) {
+        return Err(MapError::NotMapped);
+    }
+    if entry_huge(pd[i2]) {
+        if !split {
+            return Ok((&mut pd[i2], PageSize::Size2M));
+        }
+        split_huge_page(pd, i2)?;
+    }
+    let pt = table_at(entry_addr(pd[i2]));
+    if !entry_present(pt[i1]) {
+        return Err(MapError::NotMapped);
+    }
+    Ok((&mut pt[i1], PageSize::Size4K))
+}
+
+// Unmap the page containing `virt`. Addresses that are 2 MiB aligned drop the
+// whole huge page; anything else inside a huge page splits it first.
+pub fn unmap_page(virt: usize) -> Result<(usize, PageSize), MapError> {
+    unsafe {
+        let split = !virt.is_multiple_of(PAGE_SIZE_2M);
+        let (entry, size) = leaf_entry(virt, split)?;
+        let phys = entry_addr(*entry);
+        *entry = 0;
+        flush(virt);
+        Ok((phys, size))
+    }
+}
+
+// Change the permissions of the page containing `virt`, keeping its frame
+pub fn set_flags(virt: usize, flags: PageFlags) -> Result<(), MapError> {
+    unsafe {
+        let (entry, size) = leaf_entry(virt, false)?;
+        let huge = if size == PageSize::Size2M { PageFlags::HUGE } else { PageFlags::NONE };
+        *entry = (*entry & ADDR_MASK) | sanitize(flags | huge);
+    }
+    flush(virt);
+    Ok(())
+}
+
+// Same as set_flags but only for one 4 KiB page, splitting a 2 MiB page if
+// needed. This is what guard pages use.
+pub fn set_flags_4k(virt: usize, flags: PageFlags) -> Result<(), MapError> {
+    unsafe {
+        let (entry, _) = leaf_entry(virt, true)?;
+        *entry = (*entry & ADDR_MASK) | sanitize(flags);
+    }
+    flush(virt);
+    Ok(())
+}
+
+// Identity-map [start, end) with 4 KiB pages, e.g. for MMIO registers.
+// Pages that are already mapped (such as the boot identity map) are left alone.
+pub fn identity_map(start: usize, end: usize, flags: PageFlags) -> Result<(), MapError> {
+    let mut addr = start & !(PAGE_SIZE_4K - 1);
+    while addr < end {
+        if walk(addr).is_none() {
+            map_page(addr, addr, PageSize::Size4K, flags)?;
+        }
+        addr += PAGE_SIZE_4K;
+    }
+    Ok(())
+}
+
+// Identity-map device registers uncached. Unlike identity_map, pages that are
+// already covered (e.g. by the RAM identity map) get their caching fixed up.
+pub fn map_mmio(start: usize, len: usize) -> Result<(), MapError> {
+    let flags = PageFlags::WRITABLE | PageFlags::NO_CACHE | PageFlags::WRITE_THROUGH | PageFlags::NO_EXECUTE;
+    let mut addr = start & !(PAGE_SIZE_4K - 1);
+    while addr < start + len {
+        match walk(addr) {
+            None => map_page(addr, addr, PageSize::Size4K, flags)?,
+            Some(mapping) if !mapping.flags.contains(PageFlags::NO_CACHE) => set_flags_4k(addr, flags)?,
+            Some(_) => {}
+        }
+        addr += PAGE_SIZE_4K;
+    }
+    Ok(())
+}
+
+// Allocate a fresh frame and map it at `virt`
+pub fn map_new_page(virt: usize, flags: PageFlags) -> Result<Frame, MapError> {
+    let frame = memory::allocate_frame().ok_or(MapError::OutOfFrames)?;
+    if let Err(err) = map_page(virt, frame.start_address(), PageSize::Size4K, flags) {
+        memory::free_frame(frame);
+        return Err(err);
+    }
+    Ok(frame)
+}
+
+fn enable_nx() -> bool {
+    // CPUID 0x80000001 EDX bit 20 advertises the NX bit
+    if __cpuid(0x8000_0001).edx & (1 << 20) == 0 {
+        return false;
+    }
+    unsafe {
+        let efer = rdmsr(IA32_EFER);
+        wrmsr(IA32_EFER, efer | EFER_NXE);
+    }
+    true
+}
+
+// Build the kernel address space and switch CR3 to it. Must run after the
+// frame allocator has been initialised.
+pub fn init() -> Result<(), MapError> {
+    unsafe {
+        NX_ENABLED = enable_nx();
+        let pml4_phys = allocate_table(BOOT_IDENTITY_LIMIT)?;
+        let pml4 = table_at(pml4_phys);
+        let pdpt_phys = allocate_table(BOOT_IDENTITY_LIMIT)?;
+        pml4[0] = pdpt_phys as u64 | (PageFlags::PRESENT | PageFlags::WRITABLE).bits();
+        let pdpt = table_at(pdpt_phys);
+
+        // Identity-map all of physical memory up to the highest usable frame,
+        // but never less than the 1 GiB that boot.asm provided
+        let top = memory::highest_address().clamp(BOOT_IDENTITY_LIMIT, IDENTITY_MAP_LIMIT);
+        let mut addr = 0;
+        while addr < top {
+            let [_, i3, i2, _] = table_indices(addr);
+            if !entry_present(pdpt[i3]) {
+                let pd_phys = allocate_table(BOOT_IDENTITY_LIMIT)?;
+                pdpt[i3] = pd_phys as u64 | (PageFlags::PRESENT | PageFlags::WRITABLE).bits();
+            }
+            let pd = table_at(entry_addr(pdpt[i3]));
+            pd[i2] = addr as u64 | sanitize(PageFlags::WRITABLE | PageFlags::HUGE);
+            addr += PAGE_SIZE_2M;
+        }
+
+        write_cr3(pml4_phys);
+        KERNEL_PML4 = pml4_phys;
+        memory::free_contiguous(Frame::containing_address(BOOT_TABLES), BOOT_TABLE_COUNT);
+    }
+    Ok(())
+}
+
+pub fn kernel_pml4() -> usize {
+    unsafe { KERNEL_PML4 }
+}
@@ -0,0 +1,238 @@
+// --- VGA DAC palette ---
+// The DAC turns each of the 256 color indices into an RGB value. Setting
+// an index's color recolors every pixel using it at once, which is what
+// fades (move all entries towards a target over time) and color cycling
+// (rotate a range of entries) are built on. Colors are 8 bits per channel
+// here; the DAC keeps the top 6.
+//
+// Palettes can be loaded from files in two formats: raw RGB triplets (the
+// usual 768-byte .pal; if no value is above 63 they are taken as 6-bit DAC
+// values, as DOS tools save them) and JASC-PAL text.
+
+use crate::{inb, outb};
+use crate::{timer, vga};
+
+const DAC_READ_INDEX: u16 = 0x3C7;
+const DAC_WRITE_INDEX: u16 = 0x3C8;
+const DAC_DATA: u16 = 0x3C9;
+
+pub const COLORS: usize = 256;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+
+    // Blend towards `other`; `t` runs from 0 (self) to 256 (other)
+    pub fn mix(self, other: Rgb, t: u32) -> Rgb {
+        let t = t.min(256);
+        let channel = |a: u8, b: u8| ((a as u32 * (256 - t) + b as u32 * t) / 256) as u8;
+        Rgb::new(channel(self.r, other.r), channel(self.g, other.g), channel(self.b, other.b))
+    }
+}
+
+pub type Palette = [Rgb; COLORS];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PaletteError {
+    Empty,
+    // Raw data that is not whole RGB triplets
+    BadLength(usize),
+    BadHeader,
+    // JASC entry that is missing or not three numbers up to 255
+    BadEntry(usize),
+    TooManyColors(usize),
+}
+
+impl PaletteError {
+    pub fn description(&self) -> &'static str {
+        match self {
+            PaletteError::Empty => "empty palette file",
+            PaletteError::BadLength(_) => "raw palette is not made of RGB triplets",
+            PaletteError::BadHeader => "bad JASC-PAL header",
+            PaletteError::BadEntry(_) => "bad JASC-PAL color entry",
+            PaletteError::TooManyColors(_) => "more than 256 colors",
+        }
+    }
+}
+
+pub fn set_color(index: u8, color: Rgb) {
+    set_range(index, &[color]);
+}
+
+pub fn get_color(index: u8) -> Rgb {
+    let mut color = [Rgb::BLACK];
+    read_range(index, &mut color);
+    color[0]
+}
+
+// Write consecutive entries from `start`; the DAC steps the index itself.
+// Entries past 255 are dropped.
+pub fn set_range(start: u8, colors: &[Rgb]) {
+    let count = colors.len().min(COLORS - start as usize);
+    unsafe {
+        outb(DAC_WRITE_INDEX, start);
+        for color in &colors[..count] {
+            outb(DAC_DATA, color.r >> 2);
+            outb(DAC_DATA, color.g >> 2);
+            outb(DAC_DATA, color.b >> 2);
+        }
+    }
+}
+
+pub fn read_range(start: u8, colors: &mut [Rgb]) {
+    let count = colors.len().min(COLORS - start as usize);
+    // Scale 6-bit values so full intensity reads back as 255
+    let expand = |value: u8| (value & 0x3F) << 2 | (value & 0x3F) >> 4;
+    unsafe {
+        outb(DAC_READ_INDEX, start);
+        for color in &mut colors[..count] {
+            color.r = expand(inb(DAC_DATA));
+            color.g = expand(inb(DAC_DATA));
+            color.b = expand(inb(DAC_DATA));
+        }
+    }
+}
+
+pub fn save() -> Palette {
+    let mut palette = [Rgb::BLACK; COLORS];
+    read_range(0, &mut palette);
+    palette
+}
+
+pub fn load(palette: &Palette) {
+    set_range(0, palette);
+}
+
+// Fill `count` entries from `start` with an even blend from `from` to `to`,
+// e.g. for fb_draw_gradient_rect over those indices
+pub fn ramp(start: u8, count: usize, from: Rgb, to: Rgb) {
+    let count = count.min(COLORS - start as usize);
+    let mut colors = [Rgb::BLACK; COLORS];
+    for (i, color) in colors[..count].iter_mut().enumerate() {
+        let t = if count > 1 { (i * 256 / (count - 1)) as u32 } else { 0 };
+        *color = from.mix(to, t);
+    }
+    set_range(start, &colors[..count]);
+}
+
+// Rotate entries `start..=end` by `steps` (positive moves each color to a
+// higher index, the last one wrapping to `start`), for color cycling
+pub fn rotate_range(start: u8, end: u8, steps: isize) {
+    if end <= start {
+        return;
+    }
+    let len = (end - start) as usize + 1;
+    let mut colors = [Rgb::BLACK; COLORS];
+    read_range(start, &mut colors[..len]);
+    colors[..len].rotate_right(steps.rem_euclid(len as isize) as usize);
+    set_range(start, &colors[..len]);
+}
+
+// A fade in progress; call step() once per frame until it returns true
+pub struct Fade {
+    from: Palette,
+    to: Palette,
+    start_ms: u64,
+    duration_ms: u64,
+}
+
+impl Fade {
+    // Fade from the current DAC contents to `to`
+    pub fn new(to: &Palette, duration_ms: u64) -> Fade {
+        Fade { from: save(), to: *to, start_ms: timer::uptime_ms(), duration_ms }
+    }
+
+    // Load the palette for the current time. Without a running timer the
+    // fade completes at once.
+    pub fn step(&self) -> bool {
+        let elapsed = timer::uptime_ms().saturating_sub(self.start_ms);
+        let done = timer::frequency() == 0 || elapsed >= self.duration_ms;
+        let t = if done { 256 } else { (elapsed * 256 / self.duration_ms) as u32 };
+        let mut palette = [Rgb::BLACK; COLORS];
+        for (i, color) in palette.iter_mut().enumerate() {
+            *color = self.from[i].mix(self.to[i], t);
+        }
+        load(&palette);
+        done
+    }
+}
+
+// Fade to `to` over `duration_ms`, returning when done. The DAC is updated
+// once per frame, during vertical blank so no frame shows two palettes.
+pub fn fade_to(to: &Palette, duration_ms: u64) {
+    let fade = Fade::new(to, duration_ms);
+    loop {
+        vga::wait_for_vblank();
+        if fade.step() {
+            break;
+        }
+    }
+}
+
+pub fn fade_to_black(duration_ms: u64) {
+    fade_to(&[Rgb::BLACK; COLORS], duration_ms);
+}
+
+// Blank the screen, then bring up `to`
+pub fn fade_from_black(to: &Palette, duration_ms: u64) {
+    load(&[Rgb::BLACK; COLORS]);
+    fade_to(to, duration_ms);
+}
+
+fn parse_jasc(text: &str, palette: &mut Palette) -> Result<usize, PaletteError> {
+    let mut lines = text.lines().map(str::trim);
+    if lines.next() != Some("JASC-PAL") || lines.next() != Some("0100") {
+        return Err(PaletteError::BadHeader);
+    }
+    let count: usize = lines.next().and_then(|n| n.parse().ok()).ok_or(PaletteError::BadHeader)?;
+    if count > COLORS {
+        return Err(PaletteError::TooManyColors(count));
+    }
+    for (i, color) in palette[..count].iter_mut().enumerate() {
+        let mut values = lines.next().ok_or(PaletteError::BadEntry(i))?.split_ascii_whitespace().map(|v| v.parse::<u8>());
+        let mut next = || values.next().and_then(Result::ok).ok_or(PaletteError::BadEntry(i));
+        *color = Rgb::new(next()?, next()?, next()?);
+    }
+    Ok(count)
+}
+
+// Decode a palette file into `palette`, returning the number of colors
+pub fn parse(bytes: &[u8], palette: &mut Palette) -> Result<usize, PaletteError> {
+    if bytes.is_empty() {
+        return Err(PaletteError::Empty);
+    }
+    if bytes.starts_with(b"JASC-PAL") {
+        let text = core::str::from_utf8(bytes).map_err(|_| PaletteError::BadHeader)?;
+        return parse_jasc(text, palette);
+    }
+    if bytes.len() % 3 != 0 {
+        return Err(PaletteError::BadLength(bytes.len()));
+    }
+    let count = bytes.len() / 3;
+    if count > COLORS {
+        return Err(PaletteError::TooManyColors(count));
+    }
+    let shift = if bytes.iter().all(|&b| b <= 63) { 2 } else { 0 };
+    for (color, &[r, g, b]) in palette.iter_mut().zip(bytes.as_chunks::<3>().0) {
+        *color = Rgb::new(r << shift, g << shift, b << shift);
+    }
+    Ok(count)
+}
+
+// Load a palette file into the DAC from entry `start`
+pub fn load_file(bytes: &[u8], start: u8) -> Result<usize, PaletteError> {
+    let mut palette = [Rgb::BLACK; COLORS];
+    let count = parse(bytes, &mut palette)?;
+    set_range(start, &palette[..count]);
+    Ok(count)
+}
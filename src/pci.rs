@@ -0,0 +1,152 @@
+// --- PCI configuration space ---
+// Configuration mechanism #1: write the bus/slot/function and register
+// offset to 0xCF8, then read or write the dword at 0xCFC. Enough to find
+// devices by ID and read their BARs; firmware has already assigned bus
+// numbers and addresses, so nothing is reprogrammed.
+
+use crate::{inl, outl};
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+const REG_ID: u8 = 0x00;
+const REG_COMMAND: u8 = 0x04;
+const REG_CLASS: u8 = 0x08;
+// Header type is byte 2 of this dword
+const REG_HEADER: u8 = 0x0C;
+const REG_BAR0: u8 = 0x10;
+
+const COMMAND_MEMORY_SPACE: u32 = 0x02;
+const HEADER_MULTIFUNCTION: u8 = 0x80;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Device {
+    pub bus: u8,
+    pub slot: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub header_type: u8,
+}
+
+fn address(bus: u8, slot: u8, function: u8, offset: u8) -> u32 {
+    0x8000_0000 | (bus as u32) << 16 | (slot as u32 & 0x1F) << 11 | (function as u32 & 0x07) << 8 | (offset as u32 & 0xFC)
+}
+
+pub fn read_config(bus: u8, slot: u8, function: u8, offset: u8) -> u32 {
+    unsafe {
+        outl(CONFIG_ADDRESS, address(bus, slot, function, offset));
+        inl(CONFIG_DATA)
+    }
+}
+
+pub fn write_config(bus: u8, slot: u8, function: u8, offset: u8, value: u32) {
+    unsafe {
+        outl(CONFIG_ADDRESS, address(bus, slot, function, offset));
+        outl(CONFIG_DATA, value);
+    }
+}
+
+impl Device {
+    fn read(&self, offset: u8) -> u32 {
+        read_config(self.bus, self.slot, self.function, offset)
+    }
+
+    fn write(&self, offset: u8, value: u32) {
+        write_config(self.bus, self.slot, self.function, offset, value);
+    }
+
+    fn multifunction(&self) -> bool {
+        self.header_type & HEADER_MULTIFUNCTION != 0
+    }
+
+    // Physical address of memory BAR `index`; None for I/O and unassigned
+    // BARs. A 64-bit BAR takes its upper half from the next slot.
+    pub fn memory_bar(&self, index: u8) -> Option<usize> {
+        if index > 5 {
+            return None;
+        }
+        let low = self.read(REG_BAR0 + index * 4);
+        if low & 0x01 != 0 {
+            return None;
+        }
+        let mut addr = (low & !0x0F) as u64;
+        if low & 0x06 == 0x04 && index < 5 {
+            addr |= (self.read(REG_BAR0 + (index + 1) * 4) as u64) << 32;
+        }
+        if addr == 0 { None } else { Some(addr as usize) }
+    }
+
+    // Size of memory BAR `index`, found the usual way: write all ones and
+    // see which address bits stick, then restore it. Memory decoding is off
+    // meanwhile so the device never answers at the probe address.
+    pub fn memory_bar_size(&self, index: u8) -> Option<usize> {
+        self.memory_bar(index)?;
+        let offset = REG_BAR0 + index * 4;
+        let wide = self.read(offset) & 0x06 == 0x04 && index < 5;
+        let command = self.read(REG_COMMAND);
+        self.write(REG_COMMAND, command & !COMMAND_MEMORY_SPACE);
+        let probe = |offset: u8| {
+            let saved = self.read(offset);
+            self.write(offset, 0xFFFF_FFFF);
+            let mask = self.read(offset);
+            self.write(offset, saved);
+            mask
+        };
+        let mut mask = (probe(offset) & !0x0F) as u64;
+        mask |= if wide { (probe(offset + 4) as u64) << 32 } else { 0xFFFF_FFFF_0000_0000 };
+        self.write(REG_COMMAND, command);
+        Some((!mask).wrapping_add(1) as usize)
+    }
+
+    // Let the device answer accesses to its memory BARs (firmware usually
+    // has already)
+    pub fn enable_memory(&self) {
+        let command = self.read(REG_COMMAND);
+        if command & COMMAND_MEMORY_SPACE == 0 {
+            self.write(REG_COMMAND, command | COMMAND_MEMORY_SPACE);
+        }
+    }
+}
+
+fn probe(bus: u8, slot: u8, function: u8) -> Option<Device> {
+    let id = read_config(bus, slot, function, REG_ID);
+    // Nothing there: the read floats to all ones
+    if id & 0xFFFF == 0xFFFF {
+        return None;
+    }
+    let class = read_config(bus, slot, function, REG_CLASS);
+    let header = read_config(bus, slot, function, REG_HEADER);
+    Some(Device {
+        bus,
+        slot,
+        function,
+        vendor_id: id as u16,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        header_type: (header >> 16) as u8,
+    })
+}
+
+// Every function of every device on every bus. Functions 1-7 are only
+// probed on multifunction devices, since some single-function devices
+// answer for all eight.
+pub fn devices() -> impl Iterator<Item = Device> {
+    (0..=255u8).flat_map(|bus| {
+        (0..32u8).flat_map(move |slot| {
+            let functions = match probe(bus, slot, 0) {
+                Some(device) if device.multifunction() => 8,
+                Some(_) => 1,
+                None => 0,
+            };
+            (0..functions).filter_map(move |function| probe(bus, slot, function))
+        })
+    })
+}
+
+pub fn find(vendor_id: u16, device_id: u16) -> Option<Device> {
+    devices().find(|device| device.vendor_id == vendor_id && device.device_id == device_id)
+}
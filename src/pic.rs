@@ -0,0 +1,115 @@
+// --- 8259 Programmable Interrupt Controller ---
+// The BIOS leaves the master PIC on vectors 8-15, which collide with CPU
+// exceptions. init() remaps both chips to 32-47 and masks every line; lines
+// are unmasked one by one as drivers register handlers.
+
+use crate::{inb, io_wait, outb};
+
+pub const IRQ_BASE: u8 = 32;
+pub const IRQ_COUNT: u8 = 16;
+pub const CASCADE_IRQ: u8 = 2;
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC2_DATA: u16 = 0xA1;
+
+const ICW1_ICW4: u8 = 0x01;
+const ICW1_INIT: u8 = 0x10;
+const ICW4_8086: u8 = 0x01;
+const OCW3_READ_ISR: u8 = 0x0B;
+const PIC_EOI: u8 = 0x20;
+
+pub fn init() {
+    unsafe {
+        // ICW1: start initialisation, expect ICW4
+        outb(PIC1_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        outb(PIC2_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        // ICW2: vector offsets
+        outb(PIC1_DATA, IRQ_BASE);
+        io_wait();
+        outb(PIC2_DATA, IRQ_BASE + 8);
+        io_wait();
+        // ICW3: slave on IRQ2, slave cascade identity 2
+        outb(PIC1_DATA, 1 << CASCADE_IRQ);
+        io_wait();
+        outb(PIC2_DATA, CASCADE_IRQ);
+        io_wait();
+        // ICW4: 8086 mode
+        outb(PIC1_DATA, ICW4_8086);
+        io_wait();
+        outb(PIC2_DATA, ICW4_8086);
+        io_wait();
+        // Everything masked until a handler asks for it
+        outb(PIC1_DATA, 0xFF);
+        outb(PIC2_DATA, 0xFF);
+    }
+}
+
+// Mask every line, e.g. before handing interrupts over to the APIC
+pub fn disable() {
+    unsafe {
+        outb(PIC1_DATA, 0xFF);
+        outb(PIC2_DATA, 0xFF);
+    }
+}
+
+fn data_port(irq: u8) -> (u16, u8) {
+    if irq < 8 { (PIC1_DATA, irq) } else { (PIC2_DATA, irq - 8) }
+}
+
+pub fn mask(irq: u8) {
+    if irq >= IRQ_COUNT {
+        return;
+    }
+    let (port, line) = data_port(irq);
+    unsafe { outb(port, inb(port) | (1 << line)); }
+}
+
+pub fn unmask(irq: u8) {
+    if irq >= IRQ_COUNT {
+        return;
+    }
+    let (port, line) = data_port(irq);
+    unsafe { outb(port, inb(port) & !(1 << line)); }
+    // Slave lines only reach the CPU through the cascade input
+    if irq >= 8 {
+        unmask(CASCADE_IRQ);
+    }
+}
+
+pub fn end_of_interrupt(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            outb(PIC2_COMMAND, PIC_EOI);
+        }
+        outb(PIC1_COMMAND, PIC_EOI);
+    }
+}
+
+// In-service register of both chips, slave in the high byte
+fn read_isr() -> u16 {
+    unsafe {
+        outb(PIC1_COMMAND, OCW3_READ_ISR);
+        outb(PIC2_COMMAND, OCW3_READ_ISR);
+        ((inb(PIC2_COMMAND) as u16) << 8) | inb(PIC1_COMMAND) as u16
+    }
+}
+
+// IRQ7 and IRQ15 are raised when a request disappears before the CPU
+// acknowledged it. Such an IRQ is not in service and must not get a normal
+// EOI, although a spurious IRQ15 still needs one for the master's cascade.
+pub fn is_spurious(irq: u8) -> bool {
+    if irq != 7 && irq != 15 {
+        return false;
+    }
+    if read_isr() & (1 << irq) != 0 {
+        return false;
+    }
+    if irq == 15 {
+        unsafe { outb(PIC1_COMMAND, PIC_EOI); }
+    }
+    true
+}
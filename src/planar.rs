@@ -0,0 +1,173 @@
+// --- Mode 12h planar backend ---
+// 640x480 in 16 colors. Each pixel is one bit in each of four planes, and
+// the byte at 0xA0000 + y * 80 + x / 8 covers eight neighbouring pixels in
+// all planes at once. Writes go through the graphics controller: write
+// mode 2 spreads a color's four bits over the planes, and the bit mask picks
+// which pixels of the byte change, the rest coming from the latches (loaded
+// by reading the byte first). Write mode 1 stores the latches unchanged,
+// which copies eight pixels of all four planes with one read and one write.
+// Every function leaves write mode 0 with all bits enabled, the state
+// set_video_mode programs.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use crate::vga::{write_graphics, write_sequencer};
+
+pub const WIDTH: usize = 640;
+pub const HEIGHT: usize = 480;
+const PITCH: usize = WIDTH / 8;
+const VGA_MEMORY: *mut u8 = 0xA0000 as *mut u8;
+
+const SEQ_MAP_MASK: u8 = 0x02;
+const GC_READ_MAP: u8 = 0x04;
+const GC_MODE: u8 = 0x05;
+const GC_BIT_MASK: u8 = 0x08;
+
+const ALL_PLANES: u8 = 0x0F;
+const WRITE_MODE_0: u8 = 0x00;
+const WRITE_MODE_1: u8 = 0x01;
+const WRITE_MODE_2: u8 = 0x02;
+
+fn begin(write_mode: u8) {
+    write_sequencer(SEQ_MAP_MASK, ALL_PLANES);
+    write_graphics(GC_MODE, write_mode);
+}
+
+fn end() {
+    write_graphics(GC_BIT_MASK, 0xFF);
+    write_graphics(GC_MODE, WRITE_MODE_0);
+}
+
+// Set the pixels selected by `mask` in the byte at `offset` to `color`.
+// Needs write mode 2.
+fn write_masked(offset: usize, mask: u8, color: u8) {
+    write_graphics(GC_BIT_MASK, mask);
+    unsafe {
+        let byte = VGA_MEMORY.add(offset);
+        // The value read is unused; the read loads the latches
+        read_volatile(byte);
+        write_volatile(byte, color);
+    }
+}
+
+// One row of a fill, already clipped; needs write mode 2
+fn span(x: usize, end_x: usize, y: usize, color: u8) {
+    let row = y * PITCH;
+    let (first, last) = (x / 8, (end_x - 1) / 8);
+    let left = 0xFFu8 >> (x & 7);
+    let right = 0xFFu8 << (7 - ((end_x - 1) & 7));
+    if first == last {
+        write_masked(row + first, left & right, color);
+        return;
+    }
+    write_masked(row + first, left, color);
+    // Whole bytes need no latches
+    write_graphics(GC_BIT_MASK, 0xFF);
+    for byte in first + 1..last {
+        unsafe { write_volatile(VGA_MEMORY.add(row + byte), color); }
+    }
+    write_masked(row + last, right, color);
+}
+
+pub fn set_pixel(x: usize, y: usize, color: u8) {
+    if x >= WIDTH || y >= HEIGHT {
+        return;
+    }
+    begin(WRITE_MODE_2);
+    write_masked(y * PITCH + x / 8, 0x80 >> (x & 7), color);
+    end();
+}
+
+pub fn get_pixel(x: usize, y: usize) -> u8 {
+    if x >= WIDTH || y >= HEIGHT {
+        return 0;
+    }
+    let offset = y * PITCH + x / 8;
+    let bit = 0x80 >> (x & 7);
+    let mut color = 0;
+    for plane in 0..4 {
+        write_graphics(GC_READ_MAP, plane);
+        if unsafe { read_volatile(VGA_MEMORY.add(offset)) } & bit != 0 {
+            color |= 1 << plane;
+        }
+    }
+    color
+}
+
+pub fn fill_rect(x: usize, y: usize, w: usize, h: usize, color: u8) {
+    let end_x = x.saturating_add(w).min(WIDTH);
+    let end_y = y.saturating_add(h).min(HEIGHT);
+    if x >= end_x || y >= end_y {
+        return;
+    }
+    begin(WRITE_MODE_2);
+    for row in y..end_y {
+        span(x, end_x, row, color);
+    }
+    end();
+}
+
+pub fn clear(color: u8) {
+    fill_rect(0, 0, WIDTH, HEIGHT, color);
+}
+
+// Set the pixels of a 1bpp bitmap (see crate::bitmap_bit) in `color`, one
+// masked write per screen byte instead of one per pixel
+pub fn draw_bitmap(x: usize, y: usize, w: usize, h: usize, bitmap: &[u8], color: u8) {
+    let (visible_w, visible_h) = crate::clip_area(x, y, w, h);
+    begin(WRITE_MODE_2);
+    for row in 0..visible_h {
+        let offset = (y + row) * PITCH;
+        let mut byte = x / 8;
+        let mut mask = 0u8;
+        for col in 0..visible_w {
+            let px = x + col;
+            if px / 8 != byte {
+                if mask != 0 {
+                    write_masked(offset + byte, mask, color);
+                }
+                byte = px / 8;
+                mask = 0;
+            }
+            if crate::bitmap_bit(bitmap, w, col, row) {
+                mask |= 0x80 >> (px & 7);
+            }
+        }
+        if mask != 0 {
+            write_masked(offset + byte, mask, color);
+        }
+    }
+    end();
+}
+
+// Copy an area already clipped by fb_blit, in crate::copy_direction order.
+// Copies on byte boundaries move eight pixels at a time through the latches.
+pub fn copy_rect(src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, w: usize, h: usize) {
+    let (rows_up, cols_back) = crate::copy_direction(src_x, src_y, dst_x, dst_y);
+    let row_at = |i: usize| if rows_up { h - 1 - i } else { i };
+    if src_x % 8 == 0 && dst_x % 8 == 0 && w % 8 == 0 {
+        let bytes = w / 8;
+        begin(WRITE_MODE_1);
+        for i in 0..h {
+            let row = row_at(i);
+            let src = (src_y + row) * PITCH + src_x / 8;
+            let dst = (dst_y + row) * PITCH + dst_x / 8;
+            for j in 0..bytes {
+                let byte = if cols_back { bytes - 1 - j } else { j };
+                unsafe {
+                    read_volatile(VGA_MEMORY.add(src + byte));
+                    write_volatile(VGA_MEMORY.add(dst + byte), 0);
+                }
+            }
+        }
+        end();
+    } else {
+        for i in 0..h {
+            let row = row_at(i);
+            for j in 0..w {
+                let col = if cols_back { w - 1 - j } else { j };
+                set_pixel(dst_x + col, dst_y + row, get_pixel(src_x + col, src_y + row));
+            }
+        }
+    }
+}
@@ -0,0 +1,342 @@
+// --- i8042 PS/2 controller ---
+// Brings the controller up from a known state instead of trusting whatever
+// the firmware left behind: both ports are disabled and flushed, the
+// controller and each port run their self-tests, and the device on each port
+// is reset and identified. Only ports with a working device get their IRQ
+// enabled. Drivers then talk to their device through send_command().
+
+use crate::{inb, outb};
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+// Set when the byte in the output buffer came from the second port
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_SECOND: u8 = 0xA7;
+const CMD_ENABLE_SECOND: u8 = 0xA8;
+const CMD_TEST_SECOND: u8 = 0xA9;
+const CMD_SELF_TEST: u8 = 0xAA;
+const CMD_TEST_FIRST: u8 = 0xAB;
+const CMD_DISABLE_FIRST: u8 = 0xAD;
+const CMD_ENABLE_FIRST: u8 = 0xAE;
+const CMD_WRITE_SECOND: u8 = 0xD4;
+
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+const CONFIG_SECOND_CLOCK_OFF: u8 = 1 << 5;
+// Convert the keyboard's scancode set 2 to set 1, which keymap decodes
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+const DEVICE_RESET: u8 = 0xFF;
+const DEVICE_IDENTIFY: u8 = 0xF2;
+const DEVICE_ENABLE_SCANNING: u8 = 0xF4;
+const DEVICE_DISABLE_SCANNING: u8 = 0xF5;
+const DEVICE_ACK: u8 = 0xFA;
+const DEVICE_RESEND: u8 = 0xFE;
+const DEVICE_BAT_PASSED: u8 = 0xAA;
+
+// Polling budgets; a reset runs the device's power-on self-test, which can
+// take hundreds of milliseconds on real keyboards
+const TIMEOUT: u32 = 100_000;
+const RESET_TIMEOUT: u32 = 2_000_000;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Port {
+    First,
+    Second,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Device {
+    None,
+    Keyboard,
+    Mouse,
+    WheelMouse,
+    FiveButtonMouse,
+    Unknown(u8),
+}
+
+impl Device {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Device::None => "nothing attached",
+            Device::Keyboard => "keyboard",
+            Device::Mouse => "mouse",
+            Device::WheelMouse => "wheel mouse",
+            Device::FiveButtonMouse => "5-button mouse",
+            Device::Unknown(_) => "unknown device",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Ps2Error {
+    NoController,
+    Timeout,
+    ControllerTestFailed(u8),
+    PortTestFailed(u8),
+    NoSecondPort,
+    DeviceResetFailed(u8),
+    NoAck(u8),
+}
+
+impl Ps2Error {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Ps2Error::NoController => "no i8042 controller",
+            Ps2Error::Timeout => "controller timed out",
+            Ps2Error::ControllerTestFailed(_) => "controller self-test failed",
+            Ps2Error::PortTestFailed(_) => "port self-test failed",
+            Ps2Error::NoSecondPort => "port not present",
+            Ps2Error::DeviceResetFailed(_) => "device self-test failed",
+            Ps2Error::NoAck(_) => "device did not acknowledge",
+        }
+    }
+}
+
+static mut PORTS: [Result<Device, Ps2Error>; 2] = [Ok(Device::None), Ok(Device::None)];
+// Takes mouse bytes that turn up while a command to the first port waits
+// for its reply
+static mut AUX_HANDLER: Option<fn(u8)> = None;
+
+fn status() -> u8 {
+    unsafe { inb(STATUS_PORT) }
+}
+
+fn wait_input_empty() -> Result<(), Ps2Error> {
+    for _ in 0..TIMEOUT {
+        if status() & STATUS_INPUT_FULL == 0 {
+            return Ok(());
+        }
+    }
+    Err(Ps2Error::Timeout)
+}
+
+fn read_data_within(budget: u32) -> Option<u8> {
+    for _ in 0..budget {
+        if status() & STATUS_OUTPUT_FULL != 0 {
+            return Some(unsafe { inb(DATA_PORT) });
+        }
+    }
+    None
+}
+
+// Wait for a byte from the controller or a device
+pub fn read_data() -> Option<u8> {
+    read_data_within(TIMEOUT)
+}
+
+// Wait for a reply from the device on `port`. Once a mouse is attached its
+// bytes can arrive in between; they go to the AUX handler (or are dropped)
+// instead of being taken for the keyboard's reply.
+fn read_reply(port: Port) -> Option<u8> {
+    let mouse = device(Port::Second) != Device::None;
+    for _ in 0..TIMEOUT {
+        let status = status();
+        if status & STATUS_OUTPUT_FULL == 0 {
+            continue;
+        }
+        let byte = unsafe { inb(DATA_PORT) };
+        if port == Port::First && mouse && status & STATUS_AUX_DATA != 0 {
+            if let Some(handler) = unsafe { AUX_HANDLER } {
+                handler(byte);
+            }
+            continue;
+        }
+        return Some(byte);
+    }
+    None
+}
+
+// Register where mouse bytes read while talking to the keyboard go
+pub fn set_aux_handler(handler: fn(u8)) {
+    unsafe { AUX_HANDLER = Some(handler); }
+}
+
+fn write_controller(command: u8) -> Result<(), Ps2Error> {
+    wait_input_empty()?;
+    unsafe { outb(COMMAND_PORT, command); }
+    Ok(())
+}
+
+fn write_data(byte: u8) -> Result<(), Ps2Error> {
+    wait_input_empty()?;
+    unsafe { outb(DATA_PORT, byte); }
+    Ok(())
+}
+
+// Send a controller command that answers with one byte
+fn query_controller(command: u8) -> Result<u8, Ps2Error> {
+    write_controller(command)?;
+    read_data().ok_or(Ps2Error::Timeout)
+}
+
+pub fn read_config() -> Result<u8, Ps2Error> {
+    query_controller(CMD_READ_CONFIG)
+}
+
+pub fn write_config(config: u8) -> Result<(), Ps2Error> {
+    write_controller(CMD_WRITE_CONFIG)?;
+    write_data(config)
+}
+
+fn flush_output() {
+    for _ in 0..TIMEOUT {
+        if status() & STATUS_OUTPUT_FULL == 0 {
+            return;
+        }
+        unsafe { inb(DATA_PORT); }
+    }
+}
+
+fn write_device(port: Port, byte: u8) -> Result<(), Ps2Error> {
+    if port == Port::Second {
+        write_controller(CMD_WRITE_SECOND)?;
+    }
+    write_data(byte)
+}
+
+// Send a byte to the device on `port` and wait for its ACK, resending a few
+// times if asked to. Bytes other than ACK/Resend that the device sends
+// meanwhile (e.g. keys pressed at that moment) are handed to `unexpected`.
+pub fn send_command_with(port: Port, byte: u8, unexpected: fn(u8)) -> Result<(), Ps2Error> {
+    for _ in 0..3 {
+        write_device(port, byte)?;
+        loop {
+            match read_reply(port) {
+                Some(DEVICE_ACK) => return Ok(()),
+                Some(DEVICE_RESEND) => break,
+                Some(other) => unexpected(other),
+                None => return Err(Ps2Error::NoAck(byte)),
+            }
+        }
+    }
+    Err(Ps2Error::NoAck(byte))
+}
+
+pub fn send_command(port: Port, byte: u8) -> Result<(), Ps2Error> {
+    send_command_with(port, byte, |_| {})
+}
+
+// Reset the device and run its self-test. A port with nothing plugged in
+// simply never answers.
+fn reset_device(port: Port) -> Result<Device, Ps2Error> {
+    write_device(port, DEVICE_RESET)?;
+    match read_data_within(RESET_TIMEOUT) {
+        Some(DEVICE_ACK) => {}
+        None => return Ok(Device::None),
+        Some(other) => return Err(Ps2Error::NoAck(other)),
+    }
+    match read_data_within(RESET_TIMEOUT) {
+        Some(DEVICE_BAT_PASSED) => {}
+        Some(code) => return Err(Ps2Error::DeviceResetFailed(code)),
+        None => return Err(Ps2Error::Timeout),
+    }
+    // Mice follow the self-test result with their ID
+    read_data();
+    identify(port)
+}
+
+fn identify(port: Port) -> Result<Device, Ps2Error> {
+    send_command(port, DEVICE_DISABLE_SCANNING)?;
+    send_command(port, DEVICE_IDENTIFY)?;
+    let device = match (read_data(), read_data()) {
+        // Ancient AT keyboards send no ID at all
+        (None, _) => Device::Keyboard,
+        (Some(0xAB), _) => Device::Keyboard,
+        (Some(0x00), _) => Device::Mouse,
+        (Some(0x03), _) => Device::WheelMouse,
+        (Some(0x04), _) => Device::FiveButtonMouse,
+        (Some(id), _) => Device::Unknown(id),
+    };
+    // Keyboards scan right away; mice stay quiet until their driver is ready
+    if device == Device::Keyboard {
+        send_command(port, DEVICE_ENABLE_SCANNING)?;
+    }
+    Ok(device)
+}
+
+// Bring the controller up and detect what is attached. Errors that make the
+// whole controller unusable are returned; per-port failures are recorded and
+// available from port_status().
+pub fn init() -> Result<(), Ps2Error> {
+    // A floating bus reads as all ones
+    if status() == 0xFF {
+        return Err(Ps2Error::NoController);
+    }
+    write_controller(CMD_DISABLE_FIRST)?;
+    write_controller(CMD_DISABLE_SECOND)?;
+    flush_output();
+
+    let mut config = read_config()?;
+    config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ | CONFIG_TRANSLATION);
+    write_config(config)?;
+
+    let result = query_controller(CMD_SELF_TEST)?;
+    if result != SELF_TEST_PASSED {
+        return Err(Ps2Error::ControllerTestFailed(result));
+    }
+    // Some controllers reset themselves during the self-test
+    write_config(config)?;
+
+    // With the second port disabled its clock bit reads as set; if enabling
+    // the port clears it, the controller has two channels
+    let mut dual_channel = false;
+    if config & CONFIG_SECOND_CLOCK_OFF != 0 {
+        write_controller(CMD_ENABLE_SECOND)?;
+        dual_channel = read_config()? & CONFIG_SECOND_CLOCK_OFF == 0;
+        write_controller(CMD_DISABLE_SECOND)?;
+    }
+
+    let mut ports = [Ok(Device::None), Err(Ps2Error::NoSecondPort)];
+    let tests = [(Port::First, CMD_TEST_FIRST, CMD_ENABLE_FIRST), (Port::Second, CMD_TEST_SECOND, CMD_ENABLE_SECOND)];
+    for (i, &(port, test, enable)) in tests.iter().enumerate() {
+        if port == Port::Second && !dual_channel {
+            continue;
+        }
+        let result = query_controller(test)?;
+        ports[i] = if result != PORT_TEST_PASSED {
+            Err(Ps2Error::PortTestFailed(result))
+        } else {
+            write_controller(enable)?;
+            reset_device(port)
+        };
+    }
+    flush_output();
+
+    config = read_config()?;
+    if let Ok(Device::Keyboard) = ports[0] {
+        config |= CONFIG_TRANSLATION;
+    }
+    if matches!(ports[0], Ok(device) if device != Device::None) {
+        config |= CONFIG_FIRST_IRQ;
+    } else {
+        write_controller(CMD_DISABLE_FIRST)?;
+    }
+    if matches!(ports[1], Ok(device) if device != Device::None) {
+        config |= CONFIG_SECOND_IRQ;
+    } else if dual_channel {
+        write_controller(CMD_DISABLE_SECOND)?;
+    }
+    write_config(config)?;
+    unsafe { PORTS = ports; }
+    Ok(())
+}
+
+pub fn port_status(port: Port) -> Result<Device, Ps2Error> {
+    unsafe { PORTS[port as usize] }
+}
+
+// Device attached to `port`, or Device::None if the port is missing or broken
+pub fn device(port: Port) -> Device {
+    port_status(port).unwrap_or(Device::None)
+}
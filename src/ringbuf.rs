@@ -0,0 +1,75 @@
+// --- Lock-free single-producer/single-consumer byte queue ---
+// Meant for an interrupt handler (the only producer) feeding kernel code (the
+// only consumer). Head and tail are atomics, so neither side ever has to
+// disable interrupts. N must be a power of two; one slot is kept empty to
+// tell a full queue from an empty one.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct RingBuffer<const N: usize> {
+    data: UnsafeCell<[u8; N]>,
+    // Next slot to write, owned by the producer
+    head: AtomicUsize,
+    // Next slot to read, owned by the consumer
+    tail: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+// Safe to share because each index is only advanced by its owner
+unsafe impl<const N: usize> Sync for RingBuffer<N> {}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        assert!(N.is_power_of_two());
+        RingBuffer {
+            data: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    // Producer side. Returns false (and counts the byte as dropped) when full.
+    pub fn push(&self, byte: u8) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let next = (head + 1) & (N - 1);
+        if next == self.tail.load(Ordering::Acquire) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        unsafe { (*self.data.get())[head] = byte; }
+        self.head.store(next, Ordering::Release);
+        true
+    }
+
+    // Consumer side
+    pub fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+        let byte = unsafe { (*self.data.get())[tail] };
+        self.tail.store((tail + 1) & (N - 1), Ordering::Release);
+        Some(byte)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tail.load(Ordering::Acquire) == self.head.load(Ordering::Acquire)
+    }
+
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        head.wrapping_sub(tail) & (N - 1)
+    }
+
+    // Consumer side: discard everything currently queued
+    pub fn clear(&self) {
+        self.tail.store(self.head.load(Ordering::Acquire), Ordering::Release);
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
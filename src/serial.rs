@@ -0,0 +1,118 @@
+// --- 16550 UART on COM1 ---
+// Transmit is polled (the FIFO absorbs bursts); receive is interrupt driven
+// into a ring buffer like the keyboard. Once initialised, everything printed
+// to the VGA console is mirrored here, so the kernel log survives the switch
+// to graphics mode and can be captured with `qemu -serial stdio`.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::interrupts::InterruptFrame;
+use crate::irq;
+use crate::ringbuf::RingBuffer;
+use crate::{inb, outb};
+
+pub const COM1: u16 = 0x3F8;
+pub const DEFAULT_BAUD: u32 = 115_200;
+const UART_CLOCK: u32 = 115_200;
+
+// Register offsets from the port base
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+// With DLAB set, offsets 0 and 1 hold the baud rate divisor
+const DIVISOR_LOW: u16 = 0;
+const DIVISOR_HIGH: u16 = 1;
+
+const LCR_8N1: u8 = 0x03;
+const LCR_DLAB: u8 = 0x80;
+// Enable and clear both FIFOs, interrupt at 14 bytes
+const FCR_ENABLE_CLEAR_14: u8 = 0xC7;
+// DTR, RTS and OUT2 (which gates the IRQ line on PCs)
+const MCR_NORMAL: u8 = 0x0B;
+const MCR_LOOPBACK: u8 = 0x1E;
+const IER_RECEIVED_DATA: u8 = 0x01;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+// Give up on a byte rather than hang if the UART stops draining
+const TRANSMIT_TIMEOUT: u32 = 100_000;
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+static RECEIVED: RingBuffer<256> = RingBuffer::new();
+
+fn serial_interrupt(_frame: &mut InterruptFrame) {
+    unsafe {
+        while inb(COM1 + LINE_STATUS) & LSR_DATA_READY != 0 {
+            RECEIVED.push(inb(COM1 + DATA));
+        }
+    }
+}
+
+// Program COM1 for `baud` 8N1 and check it with a loopback test. Returns
+// false if there is no working UART; output is then silently dropped.
+pub fn init(baud: u32) -> bool {
+    let divisor = (UART_CLOCK / baud.max(1)).clamp(1, 0xFFFF) as u16;
+    unsafe {
+        outb(COM1 + INTERRUPT_ENABLE, 0);
+        outb(COM1 + LINE_CONTROL, LCR_DLAB);
+        outb(COM1 + DIVISOR_LOW, divisor as u8);
+        outb(COM1 + DIVISOR_HIGH, (divisor >> 8) as u8);
+        outb(COM1 + LINE_CONTROL, LCR_8N1);
+        outb(COM1 + FIFO_CONTROL, FCR_ENABLE_CLEAR_14);
+
+        outb(COM1 + MODEM_CONTROL, MCR_LOOPBACK);
+        outb(COM1 + DATA, 0xAE);
+        if inb(COM1 + DATA) != 0xAE {
+            return false;
+        }
+        outb(COM1 + MODEM_CONTROL, MCR_NORMAL);
+        // Drop anything received before we were listening
+        while inb(COM1 + LINE_STATUS) & LSR_DATA_READY != 0 {
+            inb(COM1 + DATA);
+        }
+    }
+    INITIALIZED.store(true, Ordering::Release);
+    if irq::register_handler(irq::IRQ_COM1, serial_interrupt) {
+        unsafe { outb(COM1 + INTERRUPT_ENABLE, IER_RECEIVED_DATA); }
+    }
+    true
+}
+
+pub fn is_initialized() -> bool {
+    INITIALIZED.load(Ordering::Acquire)
+}
+
+pub fn write_byte(byte: u8) {
+    if !is_initialized() {
+        return;
+    }
+    unsafe {
+        for _ in 0..TRANSMIT_TIMEOUT {
+            if inb(COM1 + LINE_STATUS) & LSR_TRANSMIT_EMPTY != 0 {
+                break;
+            }
+        }
+        outb(COM1 + DATA, byte);
+    }
+}
+
+// Terminals expect CR LF line endings
+pub fn write_str(s: &str) {
+    for byte in s.bytes() {
+        if byte == b'\n' {
+            write_byte(b'\r');
+        }
+        write_byte(byte);
+    }
+}
+
+pub fn read_byte() -> Option<u8> {
+    RECEIVED.pop()
+}
+
+pub fn has_data() -> bool {
+    !RECEIVED.is_empty()
+}
@@ -0,0 +1,78 @@
+// --- PIT system tick ---
+// Channel 0 of the 8253/8254 drives IRQ0 at a configurable rate. Each tick
+// adds the tick period in nanoseconds to a monotonic clock, so milliseconds
+// stay accurate when the divisor does not divide the PIT input clock evenly
+// and across frequency changes at runtime.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::interrupts::InterruptFrame;
+use crate::irq;
+use crate::outb;
+
+pub const PIT_BASE_FREQUENCY: u32 = 1_193_182;
+pub const DEFAULT_FREQUENCY: u32 = 1000;
+
+const PIT_CHANNEL0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+// Channel 0, lobyte/hibyte access, mode 2 (rate generator), binary
+const PIT_MODE_RATE_GENERATOR: u8 = 0x34;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static ELAPSED_NS: AtomicU64 = AtomicU64::new(0);
+static TICK_NS: AtomicU64 = AtomicU64::new(0);
+static mut FREQUENCY: u32 = 0;
+
+fn timer_interrupt(_frame: &mut InterruptFrame) {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    ELAPSED_NS.fetch_add(TICK_NS.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+// Program channel 0 for roughly `hz` interrupts per second (19..=1193182)
+pub fn set_frequency(hz: u32) {
+    let divisor = (PIT_BASE_FREQUENCY / hz.max(1)).clamp(1, 65536);
+    // A divisor of 0 means 65536 to the PIT
+    let reload = if divisor == 65536 { 0 } else { divisor as u16 };
+    irq::without_interrupts(|| unsafe {
+        TICK_NS.store(divisor as u64 * 1_000_000_000 / PIT_BASE_FREQUENCY as u64, Ordering::Relaxed);
+        FREQUENCY = PIT_BASE_FREQUENCY / divisor;
+        outb(PIT_COMMAND, PIT_MODE_RATE_GENERATOR);
+        outb(PIT_CHANNEL0, reload as u8);
+        outb(PIT_CHANNEL0, (reload >> 8) as u8);
+    });
+}
+
+pub fn init(hz: u32) -> bool {
+    set_frequency(hz);
+    irq::register_handler(irq::IRQ_TIMER, timer_interrupt)
+}
+
+// Actual tick rate after rounding to a whole divisor
+pub fn frequency() -> u32 {
+    unsafe { FREQUENCY }
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime_ns() -> u64 {
+    ELAPSED_NS.load(Ordering::Relaxed)
+}
+
+// Monotonic milliseconds since init()
+pub fn uptime_ms() -> u64 {
+    uptime_ns() / 1_000_000
+}
+
+// Halt until at least `ms` milliseconds have passed. Returns immediately if
+// the timer is not running or interrupts are off, since nothing could wake us.
+pub fn sleep_ms(ms: u64) {
+    if frequency() == 0 || !irq::interrupts_enabled() {
+        return;
+    }
+    let deadline = uptime_ms() + ms;
+    while uptime_ms() < deadline {
+        unsafe { core::arch::asm!("hlt", options(nomem, nostack, preserves_flags)); }
+    }
+}
@@ -0,0 +1,280 @@
+// --- VGA register programming ---
+// Complete register sets for the standard modes we switch between at run
+// time, written straight to the hardware (no BIOS in long mode). The text
+// font lives in plane 2 of video memory, which graphics modes draw over, so
+// it is saved before leaving text mode and put back on return.
+
+use crate::timer;
+use crate::{inb, outb};
+use crate::{VGA_AC_INDEX, VGA_CRTC_DATA, VGA_CRTC_INDEX, VGA_GC_DATA, VGA_GC_INDEX};
+use crate::{VGA_INSTAT_READ, VGA_MISC_WRITE, VGA_SEQ_DATA, VGA_SEQ_INDEX};
+
+const VGA_MEMORY: *mut u8 = 0xA0000 as *mut u8;
+
+// 256 characters of 32 bytes each; 8x16 glyphs use the first 16
+const FONT_SIZE: usize = 256 * 32;
+
+pub struct Registers {
+    pub misc: u8,
+    pub sequencer: [u8; 5],
+    pub crtc: [u8; 25],
+    pub graphics: [u8; 9],
+    pub attribute: [u8; 21],
+}
+
+// 80x25 text, 9x16 cells, 0xB8000 with odd/even addressing
+pub const TEXT_80X25: Registers = Registers {
+    misc: 0x67,
+    sequencer: [0x03, 0x00, 0x03, 0x00, 0x02],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00, 0x50,
+        0x9C, 0x0E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
+        0x0C, 0x00, 0x0F, 0x08, 0x00,
+    ],
+};
+
+// 640x480, 16 colors in four planes at 0xA0000
+pub const MODE_12H: Registers = Registers {
+    misc: 0xE3,
+    sequencer: [0x03, 0x01, 0x0F, 0x00, 0x06],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0x0B, 0x3E, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xEA, 0x0C, 0xDF, 0x28, 0x00, 0xE7, 0x04, 0xE3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x0F, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
+        0x01, 0x00, 0x0F, 0x00, 0x00,
+    ],
+};
+
+// 320x200, 256 colors, one byte per pixel (chain-4) at 0xA0000
+pub const MODE_13H: Registers = Registers {
+    misc: 0x63,
+    sequencer: [0x03, 0x01, 0x0F, 0x00, 0x0E],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0xBF, 0x1F, 0x00, 0x41, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x9C, 0x0E, 0x8F, 0x28, 0x40, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0F, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+        0x41, 0x00, 0x0F, 0x00, 0x00,
+    ],
+};
+
+// Mode X: Mode 13h unchained (sequencer memory mode 0x06, CRTC byte
+// addressing) with 480-line timing, doubled to 240 rows
+pub const MODE_X: Registers = Registers {
+    misc: 0xE3,
+    sequencer: [0x03, 0x01, 0x0F, 0x00, 0x06],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0x0D, 0x3E, 0x00, 0x41, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xEA, 0xAC, 0xDF, 0x28, 0x00, 0xE7, 0x06, 0xE3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0F, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+        0x41, 0x00, 0x0F, 0x00, 0x00,
+    ],
+};
+
+pub fn registers_for(mode_id: u8) -> Option<&'static Registers> {
+    match mode_id {
+        0x03 => Some(&TEXT_80X25),
+        0x12 => Some(&MODE_12H),
+        0x13 => Some(&MODE_13H),
+        crate::MODE_X_ID => Some(&MODE_X),
+        _ => None,
+    }
+}
+
+// Input status bit 3 is set while the beam returns to the top
+pub fn in_vertical_retrace() -> bool {
+    unsafe { inb(VGA_INSTAT_READ) & 0x08 != 0 }
+}
+
+// Status reads before a wait gives up, so a missing VGA can't hang us. Each
+// read is an I/O access of about a microsecond: far longer than a frame
+// (1/70 s in the 400-line modes, 1/60 s in the 480-line ones).
+const RETRACE_POLL_LIMIT: u32 = 1_000_000;
+
+fn wait_for_retrace_state(retrace: bool) -> bool {
+    for _ in 0..RETRACE_POLL_LIMIT {
+        if in_vertical_retrace() == retrace {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+// Wait until the beam is back on the visible area
+pub fn wait_for_display() -> bool {
+    wait_for_retrace_state(false)
+}
+
+// Wait for the next vertical retrace to begin. A retrace already under way
+// is waited out first, so the caller always gets a whole blanking interval
+// to update the screen or the DAC in. Returns false if none came.
+pub fn wait_for_vblank() -> bool {
+    wait_for_display() && wait_for_retrace_state(true)
+}
+
+// Paces a render loop to the display refresh: wait() returns at the start of
+// every `interval`-th retrace, so a loop drawing one frame per call runs at
+// 70 fps in Mode 13h (35 with an interval of 2) and its frames never tear.
+// A frame that takes too long is shown a refresh later, not partly drawn.
+pub struct FramePacer {
+    interval: u32,
+    frames: u64,
+    start_ms: u64,
+}
+
+impl FramePacer {
+    pub fn new(interval: u32) -> FramePacer {
+        FramePacer { interval: interval.max(1), frames: 0, start_ms: timer::uptime_ms() }
+    }
+
+    // Wait for the next frame's retrace; also advances the animation frame
+    // counter used when no timer is running
+    pub fn wait(&mut self) {
+        for _ in 0..self.interval {
+            wait_for_vblank();
+        }
+        self.frames += 1;
+        crate::fb_update_frame_counter();
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Average frame rate since the pacer was created; 0 without a timer
+    pub fn fps(&self) -> u64 {
+        let elapsed = timer::uptime_ms().saturating_sub(self.start_ms);
+        (self.frames * 1000).checked_div(elapsed).unwrap_or(0)
+    }
+}
+
+// Graphics modes start from whatever text mode left in video memory. All of
+// them write every enabled plane, so one pass over the 64 KB window clears
+// it (in Mode X, all of its pages).
+pub fn clear_graphics_memory() {
+    for i in 0..0x10000 {
+        unsafe { *VGA_MEMORY.add(i) = 0; }
+    }
+}
+
+static mut SAVED_FONT: [u8; FONT_SIZE] = [0; FONT_SIZE];
+static mut FONT_SAVED: bool = false;
+
+pub fn write_sequencer(index: u8, value: u8) {
+    unsafe {
+        outb(VGA_SEQ_INDEX, index);
+        outb(VGA_SEQ_DATA, value);
+    }
+}
+
+pub fn write_crtc(index: u8, value: u8) {
+    unsafe {
+        outb(VGA_CRTC_INDEX, index);
+        outb(VGA_CRTC_DATA, value);
+    }
+}
+
+pub fn write_graphics(index: u8, value: u8) {
+    unsafe {
+        outb(VGA_GC_INDEX, index);
+        outb(VGA_GC_DATA, value);
+    }
+}
+
+// Load a full register set. The caller keeps interrupts off.
+pub fn write_registers(regs: &Registers) {
+    unsafe {
+        // Hold the sequencer in reset while the clock changes
+        write_sequencer(0x00, 0x01);
+        outb(VGA_MISC_WRITE, regs.misc);
+        for (i, &value) in regs.sequencer.iter().enumerate().skip(1) {
+            write_sequencer(i as u8, value);
+        }
+        write_sequencer(0x00, regs.sequencer[0]);
+
+        // Registers 0-7 are write protected by bit 7 of register 0x11
+        outb(VGA_CRTC_INDEX, 0x11);
+        outb(VGA_CRTC_DATA, inb(VGA_CRTC_DATA) & 0x7F);
+        for (i, &value) in regs.crtc.iter().enumerate() {
+            let value = if i == 0x11 { value & 0x7F } else { value };
+            write_crtc(i as u8, value);
+        }
+        // Protect them again, as the BIOS leaves them
+        write_crtc(0x11, regs.crtc[0x11]);
+
+        for (i, &value) in regs.graphics.iter().enumerate() {
+            write_graphics(i as u8, value);
+        }
+
+        // Reading the input status register resets the attribute
+        // controller's index/data flip-flop to "index"
+        for (i, &value) in regs.attribute.iter().enumerate() {
+            inb(VGA_INSTAT_READ);
+            outb(VGA_AC_INDEX, i as u8);
+            outb(VGA_AC_INDEX, value);
+        }
+        // Hand the palette back to the display, which blanks while it is
+        // being programmed
+        inb(VGA_INSTAT_READ);
+        outb(VGA_AC_INDEX, 0x20);
+    }
+}
+
+// Map plane 2 alone at 0xA0000 for reading and writing the font
+fn select_font_plane() {
+    write_sequencer(0x02, 0x04);
+    write_sequencer(0x04, 0x06);
+    write_graphics(0x04, 0x02);
+    write_graphics(0x05, 0x00);
+    write_graphics(0x06, 0x04);
+}
+
+// Back to the text mode's odd/even mapping at 0xB8000
+fn deselect_font_plane() {
+    let text = &TEXT_80X25;
+    write_sequencer(0x02, text.sequencer[2]);
+    write_sequencer(0x04, text.sequencer[4]);
+    write_graphics(0x04, text.graphics[4]);
+    write_graphics(0x05, text.graphics[5]);
+    write_graphics(0x06, text.graphics[6]);
+}
+
+// Keep the text font before a graphics mode overwrites it. Must be called
+// while the text mode registers are loaded.
+pub fn save_font() {
+    select_font_plane();
+    unsafe {
+        for (i, byte) in SAVED_FONT.iter_mut().enumerate() {
+            *byte = *VGA_MEMORY.add(i);
+        }
+        FONT_SAVED = true;
+    }
+    deselect_font_plane();
+}
+
+// Put the saved font back after loading the text mode registers
+pub fn restore_font() {
+    if unsafe { !FONT_SAVED } {
+        return;
+    }
+    select_font_plane();
+    unsafe {
+        for (i, &byte) in SAVED_FONT.iter().enumerate() {
+            *VGA_MEMORY.add(i) = byte;
+        }
+    }
+    deselect_font_plane();
+}
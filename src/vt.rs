@@ -0,0 +1,426 @@
+// --- Virtual terminals on the VGA text screen ---
+// Each terminal owns an 80x25 cell buffer, a cursor, its escape sequence
+// state and a scrollback ring. All of them can be written at any time; only
+// the active one is mirrored to the screen at 0xB8000. Alt+F1..F6 switch
+// between them (see keyboard.rs). The kernel log lives on the first
+// terminal, the second is reserved for an interactive shell.
+//
+// Supported escapes: SGR colors (ESC[...m), cursor position (H, f), cursor
+// movement (A-D) and erasing the screen (J) or line (K). SGR colors override
+// the color passed by the caller until reset with ESC[0m.
+
+use crate::irq;
+use crate::{inb, outb, VGA_CRTC_DATA, VGA_CRTC_INDEX};
+
+pub const WIDTH: usize = 80;
+pub const HEIGHT: usize = 25;
+pub const COUNT: usize = 6;
+pub const LOG_CONSOLE: usize = 0;
+pub const SHELL_CONSOLE: usize = 1;
+
+const CELLS: usize = WIDTH * HEIGHT;
+const TAB_WIDTH: usize = 8;
+const SCROLLBACK_LINES: usize = 500;
+const DEFAULT_COLOR: u8 = 0x2f;
+const BLANK: u16 = (DEFAULT_COLOR as u16) << 8 | b' ' as u16;
+const VGA_TEXT: *mut u16 = 0xB8000 as *mut u16;
+
+const CSI_MAX_PARAMS: usize = 4;
+// ANSI color order (black, red, green, yellow, blue, magenta, cyan, white)
+// mapped to VGA palette indices
+const ANSI_TO_VGA: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum EscapeState {
+    Normal,
+    Escape,
+    Csi,
+}
+
+// What the caller should do with a byte fed to AnsiState
+pub enum AnsiAction {
+    // Part of an escape sequence (or an SGR sequence, already applied)
+    None,
+    // An ordinary byte or control character to output
+    Byte(u8),
+    // A complete CSI sequence other than SGR; see param_or() and params
+    Csi(u8),
+}
+
+// Escape sequence parser and SGR color state, shared with the framebuffer
+// console
+pub struct AnsiState {
+    state: EscapeState,
+    pub params: [u16; CSI_MAX_PARAMS],
+    // Index of the parameter currently being parsed
+    param: usize,
+    fg: Option<u8>,
+    bg: Option<u8>,
+    bold: bool,
+}
+
+impl AnsiState {
+    pub const fn new() -> AnsiState {
+        AnsiState { state: EscapeState::Normal, params: [0; CSI_MAX_PARAMS], param: 0, fg: None, bg: None, bold: false }
+    }
+
+    // Attribute byte for `color` with the SGR overrides applied
+    pub fn attribute(&self, color: u8) -> u8 {
+        let fg = self.fg.unwrap_or(color & 0x0F) | if self.bold { 0x08 } else { 0 };
+        let bg = self.bg.unwrap_or(color >> 4);
+        bg << 4 | fg
+    }
+
+    // Numeric parameter `i`, where 0 or missing means `default`
+    pub fn param_or(&self, i: usize, default: u16) -> usize {
+        match self.params[i] {
+            0 => default as usize,
+            n => n as usize,
+        }
+    }
+
+    fn select_graphic_rendition(&mut self) {
+        for i in 0..=self.param {
+            match self.params[i] {
+                0 => {
+                    self.fg = None;
+                    self.bg = None;
+                    self.bold = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                p @ 30..=37 => self.fg = Some(ANSI_TO_VGA[(p - 30) as usize]),
+                39 => self.fg = None,
+                p @ 40..=47 => self.bg = Some(ANSI_TO_VGA[(p - 40) as usize]),
+                49 => self.bg = None,
+                p @ 90..=97 => self.fg = Some(ANSI_TO_VGA[(p - 90) as usize] | 0x08),
+                p @ 100..=107 => self.bg = Some(ANSI_TO_VGA[(p - 100) as usize] | 0x08),
+                _ => {}
+            }
+        }
+    }
+
+    // Advance the parser by one byte. A sequence may be split across calls.
+    pub fn feed(&mut self, byte: u8) -> AnsiAction {
+        match self.state {
+            EscapeState::Normal if byte == 0x1B => {
+                self.state = EscapeState::Escape;
+                AnsiAction::None
+            }
+            EscapeState::Normal => AnsiAction::Byte(byte),
+            EscapeState::Escape => {
+                self.state = if byte == b'[' { EscapeState::Csi } else { EscapeState::Normal };
+                self.params = [0; CSI_MAX_PARAMS];
+                self.param = 0;
+                AnsiAction::None
+            }
+            EscapeState::Csi => match byte {
+                b'0'..=b'9' => {
+                    let p = &mut self.params[self.param];
+                    *p = p.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                    AnsiAction::None
+                }
+                b';' => {
+                    self.param = (self.param + 1).min(CSI_MAX_PARAMS - 1);
+                    AnsiAction::None
+                }
+                // Private markers such as '?' are accepted and ignored
+                0x3C..=0x3F => AnsiAction::None,
+                0x40..=0x7E => {
+                    self.state = EscapeState::Normal;
+                    if byte == b'm' {
+                        self.select_graphic_rendition();
+                        AnsiAction::None
+                    } else {
+                        AnsiAction::Csi(byte)
+                    }
+                }
+                _ => {
+                    self.state = EscapeState::Normal;
+                    AnsiAction::None
+                }
+            },
+        }
+    }
+}
+
+struct Terminal {
+    cells: [u16; CELLS],
+    row: usize,
+    col: usize,
+    ansi: AnsiState,
+    // Lines scrolled off the top; the newest is at (written - 1) % SCROLLBACK_LINES
+    scrollback: [[u16; WIDTH]; SCROLLBACK_LINES],
+    written: usize,
+    // How many lines the view is scrolled back; 0 shows the live screen
+    offset: usize,
+    // Whether this terminal is the one on screen
+    visible: bool,
+}
+
+impl Terminal {
+    // All zeroes so the terminals live in .bss; init() blanks them
+    const fn new() -> Terminal {
+        Terminal {
+            cells: [0; CELLS],
+            row: 0,
+            col: 0,
+            ansi: AnsiState::new(),
+            scrollback: [[0; WIDTH]; SCROLLBACK_LINES],
+            written: 0,
+            offset: 0,
+            visible: false,
+        }
+    }
+
+    fn set_cell(&mut self, cell: usize, value: u16) {
+        self.cells[cell] = value;
+        if self.visible && self.offset == 0 {
+            unsafe { *VGA_TEXT.add(cell) = value; }
+        }
+    }
+
+    // Blank cells start..end (row-major indices) with the given attribute
+    fn fill(&mut self, start: usize, end: usize, color: u8) {
+        for cell in start..end.min(CELLS) {
+            self.set_cell(cell, (color as u16) << 8 | b' ' as u16);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.offset = 0;
+        self.fill(0, CELLS, DEFAULT_COLOR);
+        self.row = 0;
+        self.col = 0;
+        self.update_cursor();
+    }
+
+    fn update_cursor(&self) {
+        if self.visible && self.offset == 0 {
+            set_cursor_cell(self.row * WIDTH + self.col);
+        }
+    }
+
+    fn scroll(&mut self) {
+        // Keep the line scrolling off the top for Shift+PgUp
+        let mut line = [0; WIDTH];
+        line.copy_from_slice(&self.cells[..WIDTH]);
+        self.scrollback[self.written % SCROLLBACK_LINES] = line;
+        self.written += 1;
+        self.cells.copy_within(WIDTH.., 0);
+        self.cells[CELLS - WIDTH..].fill(BLANK);
+        if self.visible && self.offset == 0 {
+            self.render();
+        }
+    }
+
+    // Copy what this terminal shows (live screen or history) to the VGA buffer
+    fn render(&self) {
+        let available = self.written.min(SCROLLBACK_LINES);
+        let oldest = self.written - available;
+        for row in 0..HEIGHT {
+            // Rows of history followed by the live screen, viewed `offset`
+            // lines up from the bottom
+            let line = available - self.offset + row;
+            for col in 0..WIDTH {
+                let value = if line < available {
+                    self.scrollback[(oldest + line) % SCROLLBACK_LINES][col]
+                } else {
+                    self.cells[(line - available) * WIDTH + col]
+                };
+                unsafe { *VGA_TEXT.add(row * WIDTH + col) = value; }
+            }
+        }
+        if self.offset == 0 {
+            self.update_cursor();
+        } else {
+            set_cursor_cell(CELLS);
+        }
+    }
+
+    // Scroll the view by `lines` (positive goes back in history)
+    fn scroll_view(&mut self, lines: isize) {
+        let available = self.written.min(SCROLLBACK_LINES);
+        let offset = (self.offset as isize).saturating_add(lines).clamp(0, available as isize) as usize;
+        if offset != self.offset {
+            self.offset = offset;
+            if self.visible {
+                self.render();
+            }
+        }
+    }
+
+    // Run a complete CSI sequence ending in `command`
+    fn csi(&mut self, command: u8, color: u8) {
+        let attribute = self.ansi.attribute(color);
+        let cursor = self.row * WIDTH + self.col;
+        let n = self.ansi.param_or(0, 1);
+        match command {
+            b'H' | b'f' => {
+                self.row = (self.ansi.param_or(0, 1) - 1).min(HEIGHT - 1);
+                self.col = (self.ansi.param_or(1, 1) - 1).min(WIDTH - 1);
+            }
+            b'A' => self.row = self.row.saturating_sub(n),
+            b'B' => self.row = (self.row + n).min(HEIGHT - 1),
+            b'C' => self.col = (self.col + n).min(WIDTH - 1),
+            b'D' => self.col = self.col.saturating_sub(n),
+            b'J' => match self.ansi.params[0] {
+                0 => self.fill(cursor, CELLS, attribute),
+                1 => self.fill(0, cursor + 1, attribute),
+                _ => self.fill(0, CELLS, attribute),
+            },
+            b'K' => {
+                let line = self.row * WIDTH;
+                match self.ansi.params[0] {
+                    0 => self.fill(cursor, line + WIDTH, attribute),
+                    1 => self.fill(line, cursor + 1, attribute),
+                    _ => self.fill(line, line + WIDTH, attribute),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Write raw code page 437 bytes. Control characters and escape
+    // sequences are interpreted, and a sequence may be split across calls.
+    fn write(&mut self, bytes: &[u8], color: u8) {
+        // New output always shows up on the live screen
+        if self.offset != 0 {
+            self.scroll_view(isize::MIN);
+        }
+        for &byte in bytes {
+            let byte = match self.ansi.feed(byte) {
+                AnsiAction::None => continue,
+                AnsiAction::Csi(command) => {
+                    self.csi(command, color);
+                    continue;
+                }
+                AnsiAction::Byte(byte) => byte,
+            };
+            match byte {
+                b'\n' => {
+                    self.row += 1;
+                    self.col = 0;
+                }
+                b'\r' => self.col = 0,
+                0x08 => self.col = self.col.saturating_sub(1),
+                b'\t' => self.col = ((self.col / TAB_WIDTH + 1) * TAB_WIDTH).min(WIDTH - 1),
+                b => {
+                    let value = (self.ansi.attribute(color) as u16) << 8 | b as u16;
+                    self.set_cell(self.row * WIDTH + self.col, value);
+                    self.col += 1;
+                    if self.col >= WIDTH {
+                        self.row += 1;
+                        self.col = 0;
+                    }
+                }
+            }
+            // Scroll right away so the cursor never sits off screen
+            if self.row >= HEIGHT {
+                self.scroll();
+                self.row = HEIGHT - 1;
+            }
+        }
+        self.update_cursor();
+    }
+}
+
+static mut TERMINALS: [Terminal; COUNT] = [const { Terminal::new() }; COUNT];
+static mut ACTIVE: usize = LOG_CONSOLE;
+// Set while a graphics mode owns the screen; terminals still record output
+static mut SUSPENDED: bool = false;
+
+// Run `f` on terminal `index` with interrupts off, since handlers print too
+fn with_terminal<R>(index: usize, f: impl FnOnce(&mut Terminal) -> R) -> R {
+    irq::without_interrupts(|| unsafe { f(&mut TERMINALS[index.min(COUNT - 1)]) })
+}
+
+// A cell index past the end of the screen hides the cursor
+fn set_cursor_cell(pos: usize) {
+    unsafe {
+        outb(VGA_CRTC_INDEX, 0x0F);
+        outb(VGA_CRTC_DATA, pos as u8);
+        outb(VGA_CRTC_INDEX, 0x0E);
+        outb(VGA_CRTC_DATA, (pos >> 8) as u8);
+    }
+}
+
+// Show the blinking underline cursor (scanlines 14-15 of the 16-line cell)
+fn enable_cursor() {
+    unsafe {
+        outb(VGA_CRTC_INDEX, 0x0A);
+        outb(VGA_CRTC_DATA, (inb(VGA_CRTC_DATA) & 0xC0) | 14);
+        outb(VGA_CRTC_INDEX, 0x0B);
+        outb(VGA_CRTC_DATA, (inb(VGA_CRTC_DATA) & 0xE0) | 15);
+    }
+}
+
+// Blank all terminals and put the active one on screen
+pub fn init() {
+    enable_cursor();
+    for index in 0..COUNT {
+        with_terminal(index, |t| t.cells.fill(BLANK));
+    }
+    with_terminal(active(), |t| {
+        t.visible = true;
+        t.render();
+    });
+}
+
+pub fn active() -> usize {
+    unsafe { ACTIVE }
+}
+
+// Make terminal `index` the one shown on screen
+pub fn switch_to(index: usize) {
+    if index >= COUNT || index == active() {
+        return;
+    }
+    with_terminal(active(), |t| t.visible = false);
+    unsafe { ACTIVE = index; }
+    if unsafe { SUSPENDED } {
+        return;
+    }
+    with_terminal(index, |t| {
+        t.visible = true;
+        t.render();
+    });
+}
+
+// Stop drawing to the text buffer before leaving text mode
+pub fn suspend() {
+    unsafe { SUSPENDED = true; }
+    with_terminal(active(), |t| t.visible = false);
+}
+
+// Redraw the active terminal once text mode is back
+pub fn resume() {
+    unsafe { SUSPENDED = false; }
+    enable_cursor();
+    with_terminal(active(), |t| {
+        t.visible = true;
+        t.render();
+    });
+}
+
+pub fn write(index: usize, bytes: &[u8], color: u8) {
+    with_terminal(index, |t| t.write(bytes, color));
+}
+
+pub fn clear(index: usize) {
+    with_terminal(index, |t| t.clear());
+}
+
+pub fn set_cursor(index: usize, row: usize, col: usize) {
+    with_terminal(index, |t| {
+        t.row = row.min(HEIGHT - 1);
+        t.col = col.min(WIDTH - 1);
+        t.update_cursor();
+    });
+}
+
+// Page the active terminal's history half a screen at a time
+pub fn scrollback_page(up: bool) {
+    let lines = (HEIGHT / 2) as isize;
+    with_terminal(active(), |t| t.scroll_view(if up { lines } else { -lines }));
+}